@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An amount of bitcoin, stored in satoshis.
+///
+/// Using a dedicated type instead of a bare `u64` keeps satoshis from being
+/// mixed up with whole coins or plain counts. Arithmetic is only available
+/// in checked form, so an overflow is always handled by the caller.
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[serde(transparent)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    /// Number of satoshis in one bitcoin.
+    pub const SATS_PER_BTC: u64 = 100_000_000;
+
+    pub const fn from_sats(sats: u64) -> Self {
+        Amount(sats)
+    }
+
+    /// Convert a number of bitcoin to an amount, rounding to the nearest
+    /// satoshi. Returns `None` for negative, NaN or too large values.
+    pub fn from_btc(btc: f64) -> Option<Self> {
+        let sats = (btc * Self::SATS_PER_BTC as f64).round();
+        // u64::MAX as f64 rounds up to 2^64, which is already out of range
+        if !(0.0..u64::MAX as f64).contains(&sats) {
+            return None;
+        }
+        Some(Amount(sats as u64))
+    }
+
+    pub const fn to_sats(self) -> u64 {
+        self.0
+    }
+
+    pub fn to_btc(self) -> f64 {
+        self.0 as f64 / Self::SATS_PER_BTC as f64
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// Sum all the amounts, `None` if the total overflows.
+    pub fn checked_sum<I>(amounts: I) -> Option<Amount>
+    where
+        I: IntoIterator<Item = Amount>,
+    {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:08} BTC",
+            self.0 / Self::SATS_PER_BTC,
+            self.0 % Self::SATS_PER_BTC
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_reports_overflows() {
+        let max = Amount::from_sats(u64::MAX);
+        assert_eq!(max.checked_add(Amount::from_sats(1)), None);
+        assert_eq!(Amount::ZERO.checked_sub(Amount::from_sats(1)), None);
+        assert_eq!(
+            Amount::from_sats(5).checked_sub(Amount::from_sats(3)),
+            Some(Amount::from_sats(2))
+        );
+        let amounts = [1, 2, 3].map(Amount::from_sats);
+        assert_eq!(Amount::checked_sum(amounts), Some(Amount::from_sats(6)));
+        assert_eq!(Amount::checked_sum([max, Amount::from_sats(1)]), None);
+    }
+
+    #[test]
+    fn bitcoin_conversions_round_to_satoshis() {
+        assert_eq!(Amount::from_btc(1.5), Some(Amount::from_sats(150_000_000)));
+        assert_eq!(Amount::from_btc(0.000_000_014), Some(Amount::from_sats(1)));
+        assert_eq!(Amount::from_btc(-1.0), None);
+        assert_eq!(Amount::from_btc(f64::NAN), None);
+        assert_eq!(Amount::from_btc(1e12), None);
+        assert_eq!(Amount::from_sats(250_000_000).to_btc(), 2.5);
+        assert_eq!(
+            Amount::from_sats(150_000_001).to_string(),
+            "1.50000001 BTC"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+
+/// Source of the current time for the checks that depend on it, like
+/// rejecting blocks from the future. Anything but `SystemClock` is mostly
+/// useful to control time in tests and simulations.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock stopped at the given time.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
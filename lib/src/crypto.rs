@@ -9,27 +9,46 @@ use serde::{Deserialize, Serialize};
 // The algorithm can use different elliptical curve parameters, and we can us ethe so-called
 // sepc256k1, just like bitcoin
 
+// Tag of the message signed to spend an output. Signing a tagged hash
+// instead of the sighash itself keeps signatures from being valid for any
+// other use of that same hash.
+const SIGHASH_TAG: &str = "btc/sighash";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Signature(ECDSASignature<Secp256k1>);
 impl Signature {
-    //sign a create::types::TransactionOutpu from its Sha256 hash
-    pub fn sign_output(output_hash: &Hash, private_key: &PrivateKey) -> Self {
+    //sign a transaction input from its sighash, see Transaction::sighash
+    pub fn sign_output(sighash: &Hash, private_key: &PrivateKey) -> Self {
         let signing_key = &private_key.0;
-        let signature = signing_key.sign(&output_hash.as_bytes());
+        let message = Hash::tagged(SIGHASH_TAG, sighash);
+        let signature = signing_key.sign(&message.as_bytes());
         Signature(signature)
     }
 
-    pub fn verify(&self, output_hash: &Hash, public_key: &PublicKey) -> bool {
-        public_key
-            .0
-            .verify(&output_hash.as_bytes(), &self.0)
-            .is_ok()
+    pub fn verify(&self, sighash: &Hash, public_key: &PublicKey) -> bool {
+        let message = Hash::tagged(SIGHASH_TAG, sighash);
+        public_key.0.verify(&message.as_bytes(), &self.0).is_ok()
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct PublicKey(VerifyingKey<Secp256k1>);
 
+impl PublicKey {
+    /// The 33 bytes SEC1 compressed encoding of the key.
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        self.0.to_encoded_point(true).as_bytes().to_vec()
+    }
+}
+
+// VerifyingKey doesn't implement Hash, so hash its compressed encoding,
+// which is unique per key just like equality
+impl std::hash::Hash for PublicKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.to_compressed_bytes().hash(state);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PrivateKey(#[serde(with = "signkey_serde")] SigningKey<Secp256k1>);
 
@@ -38,7 +57,7 @@ impl PrivateKey {
         PrivateKey(SigningKey::random(&mut rand::thread_rng()))
     }
     pub fn public_key(&self) -> PublicKey {
-        PublicKey(self.0.verifying_key().clone())
+        PublicKey(*self.0.verifying_key())
     }
 }
 
@@ -0,0 +1,330 @@
+//! Decoding of blocks received from untrusted sources.
+//!
+//! The derived `Deserialize` of `Block` only learns how many transactions,
+//! inputs and outputs a block has once they are all decoded. Here every
+//! count is checked against the limits of the chain parameters as soon as
+//! it is read, before anything is allocated for the elements.
+//!
+//! ciborium only decodes types implementing `Deserialize` on their own, so
+//! the limits are handed to them through a thread local.
+use crate::error::BtcError;
+use crate::params::ChainParams;
+use crate::types::{
+    Block, BlockHeader, Transaction, TransactionInput, TransactionOutput,
+};
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess,
+    Visitor,
+};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Decode a block from its ciborium encoding, failing with
+/// `BlockTooLarge` as soon as it has more transactions than
+/// `max_transactions_per_block` or more outputs than `max_block_size`
+/// (an output takes more than a byte), and with `TooManySigops` as soon
+/// as it has more inputs than `max_block_sigops`. Any other malformed
+/// input is `InvalidBlockEncoding`.
+pub(crate) fn decode_block(
+    bytes: &[u8],
+    params: &ChainParams,
+) -> Result<Block, BtcError> {
+    BUDGET.with(|budget| {
+        budget
+            .transactions
+            .set(params.max_transactions_per_block as u64);
+        budget.inputs.set(params.max_block_sigops);
+        budget.outputs.set(params.max_block_size as u64);
+        budget.exceeded.set(None);
+    });
+    ciborium::from_reader(bytes)
+        .map(|BoundedBlock(block)| block)
+        .map_err(|_| match BUDGET.with(|budget| budget.exceeded.get()) {
+            Some(Limit::Transactions | Limit::Outputs) => {
+                BtcError::BlockTooLarge
+            }
+            Some(Limit::Inputs) => BtcError::TooManySigops,
+            None => BtcError::InvalidBlockEncoding,
+        })
+}
+
+thread_local! {
+    // limits of the block `decode_block` is decoding on this thread
+    static BUDGET: Budget = Budget::default();
+}
+
+// A block decoded within the limits of `BUDGET`.
+struct BoundedBlock(Block);
+
+impl<'de> Deserialize<'de> for BoundedBlock {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        BUDGET.with(|budget| {
+            BlockSeed { budget }
+                .deserialize(deserializer)
+                .map(BoundedBlock)
+        })
+    }
+}
+
+// The counts exceeding their limit map to different errors.
+#[derive(Clone, Copy)]
+enum Limit {
+    Transactions,
+    Inputs,
+    Outputs,
+}
+
+// How many more transactions, inputs and outputs the block may have, and
+// the first limit exceeded, if any.
+#[derive(Default)]
+struct Budget {
+    transactions: Cell<u64>,
+    inputs: Cell<u64>,
+    outputs: Cell<u64>,
+    exceeded: Cell<Option<Limit>>,
+}
+
+impl Budget {
+    // Count `count` more elements against `limit`.
+    fn take<E: de::Error>(&self, limit: Limit, count: usize) -> Result<(), E> {
+        let left = match limit {
+            Limit::Transactions => &self.transactions,
+            Limit::Inputs => &self.inputs,
+            Limit::Outputs => &self.outputs,
+        };
+        match left.get().checked_sub(count as u64) {
+            Some(rest) => {
+                left.set(rest);
+                Ok(())
+            }
+            None => {
+                self.exceeded.set(Some(limit));
+                Err(E::custom("too many elements"))
+            }
+        }
+    }
+}
+
+// A sequence whose elements are counted against `limit` and decoded with
+// `seed`.
+struct CountedSeq<'a, S> {
+    budget: &'a Budget,
+    limit: Limit,
+    seed: S,
+}
+
+impl<'de, S> DeserializeSeed<'de> for CountedSeq<'_, S>
+where
+    S: DeserializeSeed<'de> + Clone,
+{
+    type Value = Vec<S::Value>;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, S> Visitor<'de> for CountedSeq<'_, S>
+where
+    S: DeserializeSeed<'de> + Clone,
+{
+    type Value = Vec<S::Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<Self::Value, A::Error> {
+        // a length given upfront is checked before reading any element,
+        // otherwise they are counted one by one
+        let claimed = seq.size_hint();
+        if let Some(count) = claimed {
+            self.budget.take(self.limit, count)?;
+        }
+        let mut elements = vec![];
+        while let Some(element) = seq.next_element_seed(self.seed.clone())? {
+            if claimed.is_none() {
+                self.budget.take(self.limit, 1)?;
+            }
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+}
+
+// Decodes a block, counting its transactions.
+struct BlockSeed<'a> {
+    budget: &'a Budget,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum BlockField {
+    Header,
+    Transactions,
+    #[serde(other)]
+    Other,
+}
+
+impl<'de> DeserializeSeed<'de> for BlockSeed<'_> {
+    type Value = Block;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Block, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BlockSeed<'_> {
+    type Value = Block;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a block")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> Result<Block, A::Error> {
+        let mut header: Option<BlockHeader> = None;
+        let mut transactions = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                BlockField::Header => {
+                    if header.is_some() {
+                        return Err(de::Error::duplicate_field("header"));
+                    }
+                    header = Some(map.next_value()?);
+                }
+                BlockField::Transactions => {
+                    if transactions.is_some() {
+                        return Err(de::Error::duplicate_field("transactions"));
+                    }
+                    transactions = Some(map.next_value_seed(CountedSeq {
+                        budget: self.budget,
+                        limit: Limit::Transactions,
+                        seed: TransactionSeed {
+                            budget: self.budget,
+                        },
+                    })?);
+                }
+                BlockField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Block::new(
+            header.ok_or_else(|| de::Error::missing_field("header"))?,
+            transactions
+                .ok_or_else(|| de::Error::missing_field("transactions"))?,
+        ))
+    }
+}
+
+// Decodes a transaction, counting its inputs and outputs.
+#[derive(Clone, Copy)]
+struct TransactionSeed<'a> {
+    budget: &'a Budget,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum TransactionField {
+    Inputs,
+    Outputs,
+    LockTime,
+    CoinbaseData,
+    #[serde(other)]
+    Other,
+}
+
+impl<'de> DeserializeSeed<'de> for TransactionSeed<'_> {
+    type Value = Transaction;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Transaction, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TransactionSeed<'_> {
+    type Value = Transaction;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a transaction")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> Result<Transaction, A::Error> {
+        let mut inputs: Option<Vec<TransactionInput>> = None;
+        let mut outputs: Option<Vec<TransactionOutput>> = None;
+        let mut lock_time = None;
+        let mut coinbase_data = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                TransactionField::Inputs => {
+                    if inputs.is_some() {
+                        return Err(de::Error::duplicate_field("inputs"));
+                    }
+                    inputs = Some(map.next_value_seed(CountedSeq {
+                        budget: self.budget,
+                        limit: Limit::Inputs,
+                        seed: PhantomData,
+                    })?);
+                }
+                TransactionField::Outputs => {
+                    if outputs.is_some() {
+                        return Err(de::Error::duplicate_field("outputs"));
+                    }
+                    outputs = Some(map.next_value_seed(CountedSeq {
+                        budget: self.budget,
+                        limit: Limit::Outputs,
+                        seed: PhantomData,
+                    })?);
+                }
+                TransactionField::LockTime => {
+                    if lock_time.is_some() {
+                        return Err(de::Error::duplicate_field("lock_time"));
+                    }
+                    lock_time = Some(map.next_value()?);
+                }
+                TransactionField::CoinbaseData => {
+                    if coinbase_data.is_some() {
+                        return Err(de::Error::duplicate_field(
+                            "coinbase_data",
+                        ));
+                    }
+                    coinbase_data = Some(map.next_value()?);
+                }
+                TransactionField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        let mut transaction = Transaction::new(
+            inputs.ok_or_else(|| de::Error::missing_field("inputs"))?,
+            outputs.ok_or_else(|| de::Error::missing_field("outputs"))?,
+        );
+        transaction.lock_time =
+            lock_time.ok_or_else(|| de::Error::missing_field("lock_time"))?;
+        transaction.coinbase_data = coinbase_data
+            .ok_or_else(|| de::Error::missing_field("coinbase_data"))?;
+        Ok(transaction)
+    }
+}
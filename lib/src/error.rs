@@ -21,6 +21,26 @@ pub enum BtcError {
     InvalidPublicKey,
     #[error("Invalid private key")]
     InvalidPrivateKey,
+    #[error("Orphan block")]
+    OrphanBlock,
+    #[error("Missing undo data")]
+    MissingUndoData,
+    #[error("Block too large")]
+    BlockTooLarge,
+    #[error("Too many signature operations")]
+    TooManySigops,
+    #[error("Invalid block encoding")]
+    InvalidBlockEncoding,
+    #[error("Transaction not found")]
+    TransactionNotFound,
+    #[error("Insufficient funds")]
+    InsufficientFunds,
+    #[error("Non-standard transaction")]
+    NonStandardTransaction,
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serialization error")]
+    Serialization,
 }
 
 pub type Result<T> = std::result::Result<T, BtcError>;
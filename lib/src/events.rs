@@ -0,0 +1,32 @@
+use crate::mempool::Mempool;
+use crate::types::{Block, Transaction};
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+/// Notified by a `Blockchain` of the changes to its active chain, so
+/// wallets, indexers and mempools can follow it without polling. Every
+/// method does nothing by default.
+///
+/// A reorganization disconnects the old blocks, tip first, before
+/// connecting the new ones.
+pub trait ChainListener: Debug + Send + Sync {
+    /// `block` was connected at `height`.
+    fn on_block_connected(&self, _block: &Block, _height: u64) {}
+
+    /// `block`, which was at `height`, was disconnected.
+    fn on_block_disconnected(&self, _block: &Block, _height: u64) {}
+
+    /// `transaction` was added to the chain by the block connected at
+    /// `height`, called for each of its transactions after
+    /// `on_block_connected`.
+    fn on_transaction_added(&self, _transaction: &Transaction, _height: u64) {}
+}
+
+/// Drops the transactions a connected block confirms or conflicts with.
+impl ChainListener for Mutex<Mempool> {
+    fn on_block_connected(&self, block: &Block, _height: u64) {
+        // a panic while holding the lock leaves nothing worth protecting
+        let mut mempool = self.lock().unwrap_or_else(|e| e.into_inner());
+        mempool.remove_confirmed(block);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::clock::{Clock, SystemClock};
+use crate::error::{BtcError, Result};
+use crate::params::ChainParams;
+use crate::sha256::Hash;
+use crate::types::BlockHeader;
+use crate::U256;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A chain of block headers without their transactions, as kept by light
+/// clients and during the headers-first phase of a sync.
+///
+/// Headers are validated on their own: each must build on the previous
+/// one, have the expected target and a hash meeting it, and a timestamp
+/// after the median time past and not too far in the future. Without the
+/// transactions nothing can be said about their validity, so the chain
+/// with the most work is only assumed to be valid.
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+    // total work of the chain up to each header
+    chain_work: Vec<U256>,
+    // height of each header, by hash
+    heights: HashMap<Hash, u64>,
+    params: ChainParams,
+    // where the current time comes from
+    clock: Arc<dyn Clock>,
+}
+
+impl HeaderChain {
+    /// An empty chain following the given consensus parameters.
+    pub fn new(params: ChainParams) -> Self {
+        HeaderChain {
+            headers: vec![],
+            chain_work: vec![],
+            heights: HashMap::new(),
+            params,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Replace the system clock used to reject headers too far in the
+    /// future, e.g. with a `FixedClock` in tests.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Append a header extending the tip, or the genesis header if the
+    /// chain is empty. Fails with `InvalidBlockHeader` if it isn't valid.
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<()> {
+        let height = self.len();
+        let prev_hash = self.tip().map_or(Hash::zero(), BlockHeader::hash);
+        if header.prev_block_hash != prev_hash {
+            println!("header doesn't build on the tip");
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        let hash = header.hash();
+        if !self.params.matches_checkpoint(height, &hash) {
+            println!("header contradicts a checkpoint");
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        if header.target != self.next_target() {
+            println!("wrong target");
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        if !hash.matches_target(header.target) {
+            println!("does not match target");
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        let too_far_in_future = header.timestamp
+            > self.clock.now()
+                + chrono::Duration::seconds(self.params.max_future_block_time);
+        if header.timestamp <= self.median_time_past() || too_far_in_future {
+            println!("invalid header timestamp");
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        self.chain_work
+            .push(self.total_work().saturating_add(header.work()));
+        self.heights.insert(hash, height);
+        self.headers.push(header);
+        Ok(())
+    }
+
+    /// Append several headers with `add_header`. If any is invalid none of
+    /// them are added.
+    pub fn add_headers(&mut self, headers: Vec<BlockHeader>) -> Result<()> {
+        let initial_len = self.headers.len();
+        for header in headers {
+            if let Err(e) = self.add_header(header) {
+                for header in self.headers.drain(initial_len..) {
+                    self.heights.remove(&header.hash());
+                }
+                self.chain_work.truncate(initial_len);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of headers, which is also the height of the next one.
+    pub fn len(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.headers.last()
+    }
+
+    /// The header at `height`, the genesis header's being 0.
+    pub fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    /// Height of the header `hash`, None if it isn't in the chain.
+    pub fn height_of(&self, hash: &Hash) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    /// The headers from the genesis header.
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    /// Sum of the work of every header, to compare with competing chains.
+    pub fn total_work(&self) -> U256 {
+        self.chain_work.last().copied().unwrap_or_default()
+    }
+
+    /// Total work of the chain up to the header at `height`.
+    pub fn chain_work_at(&self, height: u64) -> Option<U256> {
+        self.chain_work.get(height as usize).copied()
+    }
+
+    /// Target the next header must have.
+    pub fn next_target(&self) -> U256 {
+        next_target(&self.params, &self.headers, self.len())
+    }
+
+    /// Check the target of every header is the one `params` give for its
+    /// height: headers keep the target of the previous one, except at the
+    /// retarget heights where it must have been recalculated. Fails with
+    /// `InvalidBlockHeader` on the first header that doesn't.
+    pub fn verify_difficulty_transitions(
+        &self,
+        params: &ChainParams,
+    ) -> Result<()> {
+        for (height, header) in self.headers.iter().enumerate() {
+            if header.target
+                != next_target(params, &self.headers, height as u64)
+            {
+                return Err(BtcError::InvalidBlockHeader);
+            }
+        }
+        Ok(())
+    }
+
+    /// Median timestamp of the last `MEDIAN_TIME_SPAN` headers, which the
+    /// next one must exceed.
+    pub fn median_time_past(&self) -> DateTime<Utc> {
+        median_time_past(&self.headers, self.len())
+    }
+}
+
+// Target of the block following the first `height` blocks of `chain`,
+// retargeting every `difficulty_update_interval` blocks from how long the
+// last interval took.
+pub(crate) fn next_target<H: AsRef<BlockHeader>>(
+    params: &ChainParams,
+    chain: &[H],
+    height: u64,
+) -> U256 {
+    let Some(last_header) = (height as usize)
+        .checked_sub(1)
+        .map(|last| chain[last].as_ref())
+    else {
+        return params.min_target;
+    };
+    let target = last_header.target;
+    let interval = params.difficulty_update_interval;
+    if interval == 0 || !height.is_multiple_of(interval) {
+        return target;
+    }
+    let interval = interval as usize;
+    let first_header = chain[height as usize - interval].as_ref();
+    // n blocks are n - 1 block times apart. The parameters are public, so
+    // an ideal block time or an adjustment of 0 are taken as 1 rather than
+    // dividing by zero
+    let ideal = params
+        .ideal_block_time
+        .saturating_mul((interval as u64 - 1).max(1))
+        .max(1);
+    let adjustment = params.max_target_adjustment.max(1);
+    let elapsed = (last_header.timestamp - first_header.timestamp)
+        .num_seconds()
+        .max(0) as u64;
+    let elapsed =
+        elapsed.clamp(ideal / adjustment, ideal.saturating_mul(adjustment));
+    // multiply first to keep the precision, unless the target is too
+    // large for that
+    let new_target = match target.checked_mul(U256::from(elapsed)) {
+        Some(scaled) => scaled / U256::from(ideal),
+        None => {
+            (target / U256::from(ideal)).saturating_mul(U256::from(elapsed))
+        }
+    };
+    new_target.min(params.min_target)
+}
+
+// Median time past for the block at `height`, computed from the blocks of
+// `chain` before it. It is the unix epoch if there are none.
+pub(crate) fn median_time_past<H: AsRef<BlockHeader>>(
+    chain: &[H],
+    height: u64,
+) -> DateTime<Utc> {
+    let end = height as usize;
+    let start = end.saturating_sub(crate::MEDIAN_TIME_SPAN);
+    let mut timestamps: Vec<DateTime<Utc>> = chain[start..end]
+        .iter()
+        .map(|header| header.as_ref().timestamp)
+        .collect();
+    timestamps.sort();
+    timestamps
+        .get(timestamps.len() / 2)
+        .copied()
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Transaction;
+    use crate::util::MerkleRoot;
+
+    // Regtest parameters retargeting every 4 blocks, starting at a quarter
+    // of the hashes meeting the target so mining stays quick.
+    fn params() -> ChainParams {
+        ChainParams {
+            min_target: U256::MAX >> 2,
+            difficulty_update_interval: 4,
+            ..ChainParams::regtest()
+        }
+    }
+
+    // A header extending `chain` `seconds` after the genesis timestamp,
+    // mined for `target`.
+    fn next_header(
+        chain: &HeaderChain,
+        seconds: i64,
+        target: U256,
+    ) -> BlockHeader {
+        let coinbase = Transaction::coinbase(chain.len(), vec![]);
+        let mut header = BlockHeader::new(
+            chain.params().genesis_timestamp
+                + chrono::Duration::seconds(seconds),
+            0,
+            chain.tip().map_or(Hash::zero(), BlockHeader::hash),
+            MerkleRoot::calculate(&[coinbase]),
+            target,
+        );
+        assert!(header.mine(u64::MAX));
+        header
+    }
+
+    #[test]
+    fn targets_only_change_at_retarget_heights() {
+        let mut chain = HeaderChain::new(params());
+        let min_target = chain.params().min_target;
+        for seconds in 0..3 {
+            // the target can't change in the middle of an interval
+            let harder = next_header(&chain, seconds, min_target >> 1);
+            assert!(chain.add_header(harder).is_err());
+            chain
+                .add_header(next_header(&chain, seconds, min_target))
+                .unwrap();
+        }
+        chain
+            .add_header(next_header(&chain, 3, min_target))
+            .unwrap();
+
+        // 4 blocks 1 second apart instead of 10: the 3 seconds they took
+        // are clamped to a quarter of the ideal 30 seconds, 7
+        assert_eq!(chain.next_target(), min_target / 30 * 7);
+        let unchanged = next_header(&chain, 4, min_target);
+        assert!(chain.add_header(unchanged).is_err());
+        let retargeted = next_header(&chain, 4, chain.next_target());
+        chain.add_header(retargeted).unwrap();
+    }
+
+    #[test]
+    fn difficulty_transitions_are_checked_against_the_given_params() {
+        let mut chain = HeaderChain::new(params());
+        let min_target = chain.params().min_target;
+        for seconds in 0..4 {
+            chain
+                .add_header(next_header(&chain, seconds, min_target))
+                .unwrap();
+        }
+        let retargeted = next_header(&chain, 4, chain.next_target());
+        chain.add_header(retargeted).unwrap();
+        assert!(chain.verify_difficulty_transitions(&params()).is_ok());
+
+        // retargeting every 8 blocks, the target of block 4 changed in the
+        // middle of an interval
+        let params = ChainParams {
+            difficulty_update_interval: 8,
+            ..params()
+        };
+        assert!(matches!(
+            chain.verify_difficulty_transitions(&params),
+            Err(BtcError::InvalidBlockHeader)
+        ));
+    }
+
+    #[test]
+    fn retargeting_survives_zero_parameters() {
+        let headers: Vec<BlockHeader> = (0..4)
+            .map(|seconds| {
+                BlockHeader::new(
+                    params().genesis_timestamp
+                        + chrono::Duration::seconds(seconds),
+                    0,
+                    Hash::zero(),
+                    MerkleRoot::calculate(&[Transaction::coinbase(0, vec![])]),
+                    params().min_target >> 4,
+                )
+            })
+            .collect();
+        for (ideal_block_time, max_target_adjustment) in [(0, 4), (10, 0)] {
+            let params = ChainParams {
+                ideal_block_time,
+                max_target_adjustment,
+                ..params()
+            };
+            // the target can still be computed, and stays within bounds
+            assert!(next_target(&params, &headers, 4) <= params.min_target);
+        }
+    }
+
+    #[test]
+    fn headers_must_link_meet_their_target_and_move_forward() {
+        let mut chain = HeaderChain::new(params());
+        let target = chain.params().min_target;
+        for seconds in [0, 10, 20] {
+            chain
+                .add_header(next_header(&chain, seconds, target))
+                .unwrap();
+        }
+        let work = chain.tip().unwrap().work();
+        assert_eq!(chain.total_work(), work * 3);
+        assert_eq!(chain.chain_work_at(0), Some(work));
+        let tip = chain.tip().unwrap().hash();
+        assert_eq!(chain.height_of(&tip), Some(2));
+
+        let mut unlinked = next_header(&chain, 30, target);
+        unlinked.prev_block_hash = Hash::zero();
+        assert!(chain.add_header(unlinked).is_err());
+        let mut unmined = next_header(&chain, 30, target);
+        while unmined.hash().matches_target(target) {
+            unmined.nonce += 1;
+        }
+        assert!(chain.add_header(unmined).is_err());
+        // the median of 0, 10 and 20 seconds
+        let stale = next_header(&chain, 10, target);
+        assert!(chain.add_header(stale).is_err());
+
+        // a batch with an invalid header is dropped as a whole
+        let valid = next_header(&chain, 30, target);
+        let mut invalid = valid.clone();
+        invalid.timestamp = valid.timestamp + chrono::Duration::seconds(10);
+        invalid.prev_block_hash = Hash::zero();
+        assert!(chain.add_headers(vec![valid.clone(), invalid]).is_err());
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain.height_of(&valid.hash()), None);
+        assert_eq!(chain.total_work(), work * 3);
+    }
+}
@@ -1,10 +1,17 @@
-use serde::{Deserialize, Serialize};
-use uint::construct_uint;
-construct_uint! {
-    ///Construct a 256-bit unsigned integer.
-    /// consist of 4 64-bit words.
-    #[derive(Serialize, Deserialize)]
-    pub struct U256(4);
+use amount::Amount;
+pub use u256::U256;
+// the construct_uint! expansion trips clippy lints we can't fix from here,
+// so it lives in its own module where they can be silenced
+#[allow(clippy::manual_div_ceil, clippy::assign_op_pattern)]
+mod u256 {
+    use serde::{Deserialize, Serialize};
+    use uint::construct_uint;
+    construct_uint! {
+        ///Construct a 256-bit unsigned integer.
+        /// consist of 4 64-bit words.
+        #[derive(Serialize, Deserialize)]
+        pub struct U256(4);
+    }
 }
 // initial reward in bitcoin - multiply by 10^8 to get satoshis
 pub const INITIAL_REWARD: u64 = 50;
@@ -21,9 +28,77 @@ pub const MIN_TARGET: U256 = U256([
 ]);
 // difficulty update interval in blocks
 pub const DIFFICULTY_UPDATE_INTERVAL: u64 = 50;
+// maximum factor the target can change by in a single difficulty update
+pub const MAX_TARGET_ADJUSTMENT: u64 = 4;
+// no output, nor the outputs of a transaction together, can be worth more
+// than this, a bit over what the reward schedule ever issues
+pub const MAX_MONEY: Amount = Amount::from_sats(21_000 * Amount::SATS_PER_BTC);
+// outputs worth less than this are considered dust
+pub const DUST_THRESHOLD: Amount = Amount::from_sats(546);
+// lock times below this are block heights, above it unix timestamps
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+// sequence of an input that doesn't enforce the transaction's lock time
+pub const SEQUENCE_FINAL: u32 = 0xFFFF_FFFF;
+// sequence bit disabling the input's relative lock time (BIP68)
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+// sequence bit making the relative lock time-based instead of height-based
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+// sequence bits holding the relative lock value
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_FFFF;
+// time-based relative locks count units of 2^9 = 512 seconds
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+// how far ahead of the current time (in seconds) a block timestamp can be
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+// number of blocks the median time past is computed over
+pub const MEDIAN_TIME_SPAN: usize = 11;
+// blocks that must be mined on top of a coinbase before it can be spent,
+// see ChainParams::coinbase_maturity
+pub const COINBASE_MATURITY: u64 = 100;
+// chain height below which no coinbase can be spent on mainnet, see
+// ChainParams::min_coinbase_spend_height (0 disables it)
+pub const MIN_COINBASE_SPEND_HEIGHT: u64 = 0;
+// maximum number of blocks with an unknown parent kept waiting for it
+pub const MAX_ORPHAN_BLOCKS: usize = 100;
+// how long (in seconds) a block with an unknown parent is kept waiting
+pub const ORPHAN_EXPIRY: i64 = 20 * 60;
+// maximum size of a serialized block in bytes
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+// maximum number of transactions in a block
+pub const MAX_TRANSACTIONS_PER_BLOCK: usize = 10_000;
+// maximum size of a serialized transaction in bytes we relay or mine
+pub const MAX_STANDARD_TX_SIZE: usize = 100_000;
+// maximum number of outputs of a transaction we relay or mine
+pub const MAX_STANDARD_OUTPUTS: usize = 1_000;
+// maximum number of signatures to verify in a block
+pub const MAX_BLOCK_SIGOPS: u64 = 20_000;
+// maximum number of signatures to verify in a transaction we relay or mine
+pub const MAX_STANDARD_TX_SIGOPS: u64 = MAX_BLOCK_SIGOPS / 5;
+// top bits of a block version using version bits, the other 29 bits can
+// each signal for a soft fork deployment
+pub const VERSIONBITS_TOP_BITS: u32 = 0x2000_0000;
+// mask of the top bits of a block version
+pub const VERSIONBITS_TOP_MASK: u32 = 0xE000_0000;
+// blocks of a window that must signal for a deployment to lock in (96% of
+// the 50 DIFFICULTY_UPDATE_INTERVAL blocks of a window, as bitcoin's 95% is
+// 47.5 blocks)
+pub const RULE_CHANGE_ACTIVATION_THRESHOLD: u64 = 48;
 
+pub mod amount;
+pub mod clock;
 pub mod crypto;
+mod decode;
 pub mod error;
+pub mod events;
+pub mod headers;
+pub mod mempool;
+pub mod params;
+pub mod policy;
 pub mod sha256;
+pub mod spv;
+#[cfg(test)]
+mod test_util;
 pub mod types;
 pub mod util;
+pub mod utxo;
+pub mod validation;
+pub mod wallet;
@@ -0,0 +1,243 @@
+use crate::amount::Amount;
+use crate::error::{BtcError, Result};
+use crate::policy::StandardnessPolicy;
+use crate::sha256::Hash;
+use crate::types::{Block, Blockchain, Outpoint, Transaction};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Transactions waiting to be mined.
+///
+/// Only transactions that could go in the chain's next block are accepted,
+/// so a transaction spending an output of another pending one must wait
+/// until that one is mined.
+#[derive(Clone, Debug, Default)]
+pub struct Mempool {
+    // pending transactions by txid, with the fee they pay
+    transactions: HashMap<Hash, (Transaction, Amount)>,
+    // outputs spent by pending transactions, and the txid spending them
+    spent: HashMap<Outpoint, Hash>,
+    // relay rules, None accepts any valid transaction
+    policy: Option<StandardnessPolicy>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Mempool::default()
+    }
+
+    /// A mempool that also rejects transactions not following `policy`,
+    /// even if they would be valid in a block.
+    pub fn with_policy(policy: StandardnessPolicy) -> Self {
+        Mempool {
+            policy: Some(policy),
+            ..Mempool::default()
+        }
+    }
+
+    /// Add a transaction for the next block of `chain`. It is rejected if
+    /// `Blockchain::verify_transaction` fails, e.g. it isn't final yet, if
+    /// it spends an output another pending transaction already spends, or
+    /// if it is bigger than `MAX_STANDARD_TX_SIZE` or doesn't follow the
+    /// policy.
+    pub fn add(
+        &mut self,
+        transaction: Transaction,
+        chain: &Blockchain,
+    ) -> Result<()> {
+        // coinbase transactions are only valid in their block
+        if transaction.inputs.is_empty() {
+            return Err(BtcError::InvalidTransaction);
+        }
+        let txid = transaction.hash();
+        if self.transactions.contains_key(&txid) {
+            return Err(BtcError::InvalidTransaction);
+        }
+        // huge transactions are valid in a block, but never relayed, even
+        // without a policy
+        if transaction.serialized_size() > crate::MAX_STANDARD_TX_SIZE {
+            return Err(BtcError::NonStandardTransaction);
+        }
+        if let Some(policy) = &self.policy {
+            if !transaction.is_standard(policy) {
+                return Err(BtcError::NonStandardTransaction);
+            }
+        }
+        if transaction
+            .inputs
+            .iter()
+            .any(|input| self.spent.contains_key(&input.prev_output))
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+        chain.verify_transaction(&transaction)?;
+        let fee = transaction.fee(&chain.utxos)?;
+        for input in &transaction.inputs {
+            self.spent.insert(input.prev_output, txid);
+        }
+        self.transactions.insert(txid, (transaction, fee));
+        Ok(())
+    }
+
+    /// Txids of the pending transactions spending an output `transaction`
+    /// also spends, sorted. Empty if it can be added without replacing
+    /// anything.
+    pub fn conflicts(&self, transaction: &Transaction) -> Vec<Hash> {
+        let mut conflicts: Vec<Hash> = transaction
+            .inputs
+            .iter()
+            .filter_map(|input| self.spent.get(&input.prev_output).copied())
+            .collect();
+        conflicts.sort();
+        // several inputs can conflict with the same transaction
+        conflicts.dedup();
+        conflicts
+    }
+
+    /// Up to `max_count` pending transactions, the ones paying the highest
+    /// fee per byte first.
+    pub fn select_transactions(&self, max_count: usize) -> Vec<Transaction> {
+        let mut entries: Vec<(&Hash, &Transaction, Amount, usize)> = self
+            .transactions
+            .iter()
+            .map(|(txid, (transaction, fee))| {
+                (txid, transaction, *fee, transaction.serialized_size())
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            cmp_fee_rate((b.2, b.3), (a.2, a.3))
+                // break ties by txid so the order doesn't depend on the
+                // HashMap
+                .then(a.0.cmp(b.0))
+        });
+        entries
+            .into_iter()
+            .take(max_count)
+            .map(|(_, transaction, _, _)| transaction.clone())
+            .collect()
+    }
+
+    /// Drop the transactions included in `block`, and those spending an
+    /// output the block spends, which can never be mined anymore.
+    pub fn remove_confirmed(&mut self, block: &Block) {
+        for transaction in &block.transactions {
+            self.remove(&transaction.hash());
+            for input in &transaction.inputs {
+                if let Some(&txid) = self.spent.get(&input.prev_output) {
+                    self.remove(&txid);
+                }
+            }
+        }
+    }
+
+    /// The pending transactions, in no particular order.
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions
+            .values()
+            .map(|(transaction, _)| transaction)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    fn remove(&mut self, txid: &Hash) {
+        if let Some((transaction, _)) = self.transactions.remove(txid) {
+            for input in &transaction.inputs {
+                self.spent.remove(&input.prev_output);
+            }
+        }
+    }
+}
+
+// Compare the fee rates fee / size of two transactions without rounding,
+// by cross multiplying.
+fn cmp_fee_rate(a: (Amount, usize), b: (Amount, usize)) -> Ordering {
+    let a_rate = a.0.to_sats() as u128 * b.1 as u128;
+    let b_rate = b.0.to_sats() as u128 * a.1 as u128;
+    a_rate.cmp(&b_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+    use crate::test_util::{self, output, spend};
+
+    #[test]
+    fn double_spends_are_rejected_and_higher_fee_rates_come_first() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        test_util::mine_blocks(&mut chain, 2, &key.public_key());
+        let funds: Vec<_> = chain
+            .iter_blocks()
+            .map(test_util::coinbase_output)
+            .collect();
+        let paying = |funds: (Outpoint, Amount), fee: u64| {
+            let sats = funds.1.to_sats() - fee;
+            spend(&key, &[funds], vec![output(sats, &key.public_key())])
+        };
+
+        let mut mempool = Mempool::new();
+        let low = paying(funds[0], 1000);
+        let high = paying(funds[1], 5000);
+        let medium = paying(funds[2], 3000);
+        for transaction in [&low, &high, &medium] {
+            mempool.add(transaction.clone(), &chain).unwrap();
+        }
+        assert!(mempool.add(low.clone(), &chain).is_err());
+        let double_spend = paying(funds[0], 2000);
+        assert_eq!(mempool.conflicts(&double_spend), vec![low.hash()]);
+        assert!(matches!(
+            mempool.add(double_spend, &chain),
+            Err(BtcError::InvalidTransaction)
+        ));
+
+        let selected: Vec<Hash> = mempool
+            .select_transactions(mempool.len())
+            .iter()
+            .map(Transaction::hash)
+            .collect();
+        assert_eq!(selected, vec![high.hash(), medium.hash(), low.hash()]);
+        assert_eq!(mempool.select_transactions(1)[0].hash(), high.hash());
+
+        let block =
+            test_util::next_block(&chain, vec![high], &key.public_key());
+        mempool.remove_confirmed(&block);
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn conflicts_list_the_entries_spending_the_same_outputs() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let funds: Vec<_> = chain
+            .iter_blocks()
+            .map(test_util::coinbase_output)
+            .collect();
+        let pending =
+            spend(&key, &funds, vec![output(1000, &key.public_key())]);
+        let mut mempool = Mempool::new();
+        mempool.add(pending.clone(), &chain).unwrap();
+
+        // spending both outputs of the same entry lists it once
+        let double_spend =
+            spend(&key, &funds, vec![output(2000, &key.public_key())]);
+        assert_eq!(mempool.conflicts(&double_spend), vec![pending.hash()]);
+        let partial =
+            spend(&key, &funds[1..], vec![output(2000, &key.public_key())]);
+        assert_eq!(mempool.conflicts(&partial), vec![pending.hash()]);
+
+        let unrelated = spend(
+            &key,
+            &[(Outpoint::new(pending.hash(), 0), Amount::from_sats(1000))],
+            vec![output(500, &key.public_key())],
+        );
+        assert!(mempool.conflicts(&unrelated).is_empty());
+    }
+}
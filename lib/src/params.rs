@@ -0,0 +1,295 @@
+use crate::amount::Amount;
+use crate::sha256::Hash;
+use crate::U256;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Consensus parameters of a chain: its reward schedule, difficulty,
+/// timestamp and size rules and genesis block.
+///
+/// Blocks valid on one chain are generally invalid on another, so a
+/// `Blockchain` keeps the parameters it was created with. `mainnet` uses
+/// the crate constants; `regtest` makes mining trivial for experiments.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Block reward before any halving.
+    pub initial_reward: Amount,
+    /// Number of blocks between halvings of the block reward.
+    pub halving_interval: u64,
+    /// Ideal time between blocks in seconds.
+    pub ideal_block_time: u64,
+    /// Easiest target allowed, which is also the genesis block's.
+    pub min_target: U256,
+    /// Number of blocks between difficulty updates, 0 disables them.
+    pub difficulty_update_interval: u64,
+    /// Maximum factor the target can change by in a single update.
+    pub max_target_adjustment: u64,
+    /// How far ahead of the current time, in seconds, a block timestamp
+    /// can be.
+    pub max_future_block_time: i64,
+    /// Maximum size of a serialized block in bytes.
+    pub max_block_size: usize,
+    /// Maximum number of transactions in a block, coinbase included.
+    pub max_transactions_per_block: usize,
+    /// Maximum number of signatures to verify in a block.
+    pub max_block_sigops: u64,
+    /// Maximum value of an output, and of all the outputs of a transaction.
+    pub max_money: Amount,
+    /// Blocks that must be mined on top of a coinbase before it can be
+    /// spent.
+    pub coinbase_maturity: u64,
+    /// Height below which no coinbase can be spent, however deep, for
+    /// extra safety on fresh chains. 0 disables it.
+    pub min_coinbase_spend_height: u64,
+    /// Timestamp of the genesis block.
+    pub genesis_timestamp: DateTime<Utc>,
+    /// Hashes of known-good blocks by height. A branch with a different
+    /// block at one of these heights is rejected, however much work it has.
+    #[serde(default)]
+    pub checkpoints: BTreeMap<u64, Hash>,
+    /// Height and hash of a block whose history is trusted: the signatures
+    /// of that block and of its ancestors aren't verified, which speeds up
+    /// syncing a long chain, though every other check still runs. Blocks
+    /// are only known to be its ancestors once the chain has its header,
+    /// e.g. from `Blockchain::import_headers`. Like a checkpoint, a
+    /// different block at that height is rejected.
+    #[serde(default)]
+    pub assume_valid: Option<(u64, Hash)>,
+    /// Number of blocks in a version bits signalling window.
+    pub miner_confirmation_window: u64,
+    /// Blocks of a window that must signal for a deployment to lock in.
+    pub rule_change_activation_threshold: u64,
+    /// Soft forks activated by version bits signalling.
+    #[serde(default)]
+    pub deployments: Vec<Deployment>,
+}
+
+/// A soft fork activated once enough miners signal for it by setting a bit
+/// of their block version, as in BIP9.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Deployment {
+    pub name: String,
+    /// Bit of the block version signalling for it, from 0 to 28.
+    pub bit: u8,
+    /// Median time past from which blocks can signal.
+    pub start_time: DateTime<Utc>,
+    /// Median time past from which the deployment fails, unless it locked
+    /// in before.
+    pub timeout: DateTime<Utc>,
+}
+
+impl Deployment {
+    /// Whether a block with version `version` signals for the deployment.
+    pub fn signalled_by(&self, version: u32) -> bool {
+        version & crate::VERSIONBITS_TOP_MASK == crate::VERSIONBITS_TOP_BITS
+            && version & (1 << self.bit) != 0
+    }
+}
+
+/// State of a deployment, which only changes at the start of a window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeploymentState {
+    /// Its start time hasn't been reached.
+    Defined,
+    /// Blocks signal for it, until enough of a window do.
+    Started,
+    /// Enough blocks signalled, it activates after one more window.
+    LockedIn,
+    /// Its rules are in force.
+    Active,
+    /// It timed out before locking in.
+    Failed,
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        ChainParams {
+            initial_reward: Amount::from_sats(
+                crate::INITIAL_REWARD * Amount::SATS_PER_BTC,
+            ),
+            halving_interval: crate::HALVING_INTERVAL,
+            ideal_block_time: crate::IDEAL_BLOCK_TIME,
+            min_target: crate::MIN_TARGET,
+            difficulty_update_interval: crate::DIFFICULTY_UPDATE_INTERVAL,
+            max_target_adjustment: crate::MAX_TARGET_ADJUSTMENT,
+            max_future_block_time: crate::MAX_FUTURE_BLOCK_TIME,
+            max_block_size: crate::MAX_BLOCK_SIZE,
+            max_transactions_per_block: crate::MAX_TRANSACTIONS_PER_BLOCK,
+            max_block_sigops: crate::MAX_BLOCK_SIGOPS,
+            max_money: crate::MAX_MONEY,
+            coinbase_maturity: crate::COINBASE_MATURITY,
+            min_coinbase_spend_height: crate::MIN_COINBASE_SPEND_HEIGHT,
+            genesis_timestamp: DateTime::from_timestamp(1_704_067_200, 0)
+                .unwrap(),
+            checkpoints: BTreeMap::new(),
+            assume_valid: None,
+            miner_confirmation_window: crate::DIFFICULTY_UPDATE_INTERVAL,
+            rule_change_activation_threshold:
+                crate::RULE_CHANGE_ACTIVATION_THRESHOLD,
+            deployments: vec![],
+        }
+    }
+
+    /// Like mainnet, with a 256 times easier minimum difficulty and soft
+    /// forks locking in with 75% of a window signalling.
+    pub fn testnet() -> Self {
+        ChainParams {
+            min_target: crate::MIN_TARGET << 8 | U256::from(0xFF),
+            rule_change_activation_threshold: 38,
+            genesis_timestamp: DateTime::from_timestamp(1_704_067_201, 0)
+                .unwrap(),
+            ..Self::mainnet()
+        }
+    }
+
+    /// Local chains for experiments: any hash meets the target, which never
+    /// changes, the reward halves sooner and soft forks lock in with 75% of
+    /// a window signalling.
+    pub fn regtest() -> Self {
+        ChainParams {
+            halving_interval: 150,
+            rule_change_activation_threshold: 38,
+            min_target: U256::MAX,
+            difficulty_update_interval: 0,
+            genesis_timestamp: DateTime::from_timestamp(1_704_067_202, 0)
+                .unwrap(),
+            ..Self::mainnet()
+        }
+    }
+
+    /// Pin the block at `height` to `hash`, replacing any checkpoint
+    /// already there.
+    pub fn add_checkpoint(&mut self, height: u64, hash: Hash) {
+        self.checkpoints.insert(height, hash);
+    }
+
+    /// Whether a block with hash `hash` may be at `height`: there is no
+    /// checkpoint or assumed valid block at that height, or it is that
+    /// block.
+    pub fn matches_checkpoint(&self, height: u64, hash: &Hash) -> bool {
+        let assumed = self
+            .assume_valid
+            .filter(|(assumed_height, _)| *assumed_height == height)
+            .is_none_or(|(_, assumed_hash)| assumed_hash == *hash);
+        assumed
+            && self
+                .checkpoints
+                .get(&height)
+                .is_none_or(|checkpoint| checkpoint == hash)
+    }
+
+    /// New coins a miner is allowed to create in the block at `height`,
+    /// halving every `halving_interval` blocks. An interval of 0 means the
+    /// reward never halves.
+    pub fn block_reward(&self, height: u64) -> Amount {
+        let halvings = height.checked_div(self.halving_interval).unwrap_or(0);
+        // shifting a u64 by 64 or more bits overflows, but by then the
+        // reward has long been halved down to zero
+        let reward = u32::try_from(halvings)
+            .ok()
+            .and_then(|halvings| {
+                self.initial_reward.to_sats().checked_shr(halvings)
+            })
+            .unwrap_or(0);
+        Amount::from_sats(reward)
+    }
+
+    /// First height at which a block can spend the coinbase of the block
+    /// at `origin`: once it matured and the chain reached
+    /// `min_coinbase_spend_height`.
+    pub fn coinbase_spendable_height(&self, origin: u64) -> u64 {
+        origin
+            .saturating_add(self.coinbase_maturity)
+            .max(self.min_coinbase_spend_height)
+    }
+
+    /// Total amount ever issued by the block reward schedule, summing every
+    /// halving period until the reward reaches zero. Saturates at the
+    /// largest amount, which is also what a reward that never halves gives.
+    pub fn total_issuance(&self) -> Amount {
+        let mut reward = self.initial_reward.to_sats();
+        if self.halving_interval == 0 && reward > 0 {
+            return Amount::from_sats(u64::MAX);
+        }
+        let mut total: u64 = 0;
+        while reward > 0 {
+            total = total
+                .saturating_add(reward.saturating_mul(self.halving_interval));
+            reward /= 2;
+        }
+        Amount::from_sats(total)
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_issuance_sums_the_reward_schedule() {
+        let params = ChainParams::mainnet();
+        let summed = Amount::checked_sum(
+            (0..64 * params.halving_interval)
+                .map(|height| params.block_reward(height)),
+        )
+        .unwrap();
+        assert_eq!(params.total_issuance(), summed);
+        // a bit under 50 * 210 * 2 BTC, below the money supply limit
+        assert!(summed < Amount::from_sats(21_000 * Amount::SATS_PER_BTC));
+        assert!(summed > Amount::from_sats(20_999 * Amount::SATS_PER_BTC));
+
+        let never_halving = ChainParams {
+            halving_interval: 0,
+            ..ChainParams::mainnet()
+        };
+        assert_eq!(never_halving.total_issuance(), Amount::from_sats(u64::MAX));
+    }
+
+    #[test]
+    fn coinbases_are_spendable_once_mature_and_past_the_minimum() {
+        let params = ChainParams {
+            coinbase_maturity: 100,
+            min_coinbase_spend_height: 150,
+            ..ChainParams::mainnet()
+        };
+        assert_eq!(params.coinbase_spendable_height(0), 150);
+        assert_eq!(params.coinbase_spendable_height(60), 160);
+        assert_eq!(params.coinbase_spendable_height(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn block_rewards_halve_down_to_zero_without_overflowing() {
+        let params = ChainParams::mainnet();
+        let interval = params.halving_interval;
+        let initial = params.initial_reward;
+        assert_eq!(params.block_reward(0), initial);
+        assert_eq!(params.block_reward(interval - 1), initial);
+        assert_eq!(
+            params.block_reward(interval),
+            Amount::from_sats(initial.to_sats() / 2)
+        );
+        assert_eq!(
+            params.block_reward(2 * interval - 1),
+            Amount::from_sats(initial.to_sats() / 2)
+        );
+        assert_eq!(
+            params.block_reward(2 * interval),
+            Amount::from_sats(initial.to_sats() / 4)
+        );
+        // 50 BTC is under 2^33 sats, so it rounds down to zero after 33
+        // halvings
+        assert_ne!(params.block_reward(32 * interval), Amount::ZERO);
+        assert_eq!(params.block_reward(33 * interval), Amount::ZERO);
+        for halvings in [63, 64, 65, 1000] {
+            assert_eq!(params.block_reward(halvings * interval), Amount::ZERO);
+        }
+        assert_eq!(params.block_reward(u64::MAX), Amount::ZERO);
+    }
+}
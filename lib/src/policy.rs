@@ -0,0 +1,29 @@
+use crate::amount::Amount;
+
+/// Rules a transaction must follow to be relayed, on top of being valid.
+///
+/// Like in bitcoin, these are only policy: a block including a
+/// non-standard transaction is still valid, but nodes using a policy won't
+/// accept such transactions into their mempool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StandardnessPolicy {
+    /// Maximum number of outputs.
+    pub max_outputs: usize,
+    /// Maximum size of the serialized transaction in bytes.
+    pub max_size: usize,
+    /// Maximum number of signatures to verify.
+    pub max_sigops: u64,
+    /// Outputs worth less than this are dust, which isn't allowed.
+    pub dust_threshold: Amount,
+}
+
+impl Default for StandardnessPolicy {
+    fn default() -> Self {
+        StandardnessPolicy {
+            max_outputs: crate::MAX_STANDARD_OUTPUTS,
+            max_size: crate::MAX_STANDARD_TX_SIZE,
+            max_sigops: crate::MAX_STANDARD_TX_SIGOPS,
+            dust_threshold: crate::DUST_THRESHOLD,
+        }
+    }
+}
@@ -1,29 +1,51 @@
-use core::panic;
-
+use crate::error::{BtcError, Result};
+use crate::util;
 use crate::U256;
 use serde::{Deserialize, Serialize};
 use sha256::digest;
 use std::fmt;
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[derive(
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
 pub struct Hash(U256);
 
 impl Hash {
     //hash anything that can be serde Serialized via ciborium
+    #[allow(clippy::self_named_constructors)]
     pub fn hash<T: serde::Serialize>(data: &T) -> Self {
-        //create the buffer for storing the serialized value
-        let mut serialized: Vec<u8> = vec![];
-        if let Err(e) = ciborium::into_writer(data, &mut serialized) {
-            panic!(
-                "Failed to serialize data: {:?}. \
-            This should not happen.",
-                e
-            )
-        }
+        Self::digest(&util::serialize(data))
+    }
+
+    /// Hash `data` for a specific purpose, named by `tag`.
+    ///
+    /// Like bitcoin's tagged hashes (BIP340), the serialized data is prefixed
+    /// with `SHA256(tag) || SHA256(tag)`, so the same data hashed for two
+    /// different purposes (e.g. as an id and as the message of a signature)
+    /// can never produce the same hash.
+    pub fn tagged<T: serde::Serialize>(tag: &str, data: &T) -> Self {
+        let tag_hash = hex::decode(digest(tag)).unwrap();
+        let mut preimage = tag_hash.clone();
+        preimage.extend(&tag_hash);
+        preimage.extend(util::serialize(data));
+        Self::digest(&preimage)
+    }
+
+    /// SHA-256 hash of raw bytes, without serializing them first.
+    pub fn digest(bytes: &[u8]) -> Self {
         // Obtain the SHA-256 hash of the serialized value.
         // The hash will be a string in hexadecimal format.
         // Example: "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3"
-        let hash = digest(&serialized);
+        let hash = digest(bytes);
 
         // Convert the hexadecimal string to a vector of bytes.
         // Each hexadecimal digit pair represents a byte.
@@ -56,6 +78,25 @@ impl Hash {
 
         bytes.as_slice().try_into().unwrap()
     }
+
+    /// Inverse of `as_bytes`, the bytes are little-endian.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Hash(U256::from_little_endian(&bytes))
+    }
+
+    /// Parse the hex string written by `Display`, which leaves out leading
+    /// zeros, so 1 to 64 hex digits are accepted.
+    pub fn from_hex(s: &str) -> Result<Self> {
+        if s.is_empty()
+            || s.len() > 64
+            || !s.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(BtcError::InvalidHash);
+        }
+        U256::from_str_radix(s, 16)
+            .map(Hash)
+            .map_err(|_| BtcError::InvalidHash)
+    }
 }
 
 impl fmt::Display for Hash {
@@ -63,3 +104,47 @@ impl fmt::Display for Hash {
         write!(f, "{:x}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tagged_hashes_depend_on_the_tag() {
+        let data = 42u64;
+        let a = Hash::tagged("a", &data);
+        assert_eq!(a, Hash::tagged("a", &data));
+        assert_ne!(a, Hash::tagged("b", &data));
+        assert_ne!(a, Hash::hash(&data));
+
+        // SHA256(SHA256("a") || SHA256("a") || 0x18 0x2a), 42 being encoded
+        // as 0x18 0x2a, computed with another SHA-256 implementation
+        assert_eq!(
+            a,
+            Hash::from_hex(
+                "8d4d14de643c004f4a5d692c1dfb5cc0\
+                 48fe7a3a93fb90fcfe6806f3cbfda8aa"
+            )
+            .unwrap()
+        );
+        assert_eq!(util::serialize(&data), [0x18, 0x2a]);
+    }
+
+    #[test]
+    fn hashes_round_trip_through_bytes_and_hex() {
+        let hashes = [
+            Hash::zero(),
+            Hash::hash(&1u64),
+            Hash::hash(&"hash"),
+            Hash(U256::MAX),
+            Hash(U256::one()),
+        ];
+        for hash in hashes {
+            assert_eq!(Hash::from_bytes(hash.as_bytes()), hash);
+            assert_eq!(Hash::from_hex(&hash.to_string()).unwrap(), hash);
+        }
+        for bad in ["", "xyz", "-1", &"f".repeat(65)] {
+            assert!(matches!(Hash::from_hex(bad), Err(BtcError::InvalidHash)));
+        }
+    }
+}
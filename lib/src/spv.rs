@@ -0,0 +1,223 @@
+use crate::error::{BtcError, Result};
+use crate::headers::HeaderChain;
+use crate::params::ChainParams;
+use crate::sha256::Hash;
+use crate::types::{BlockHeader, MerkleBlock, Transaction};
+use crate::util::{self, MerkleProof};
+use std::collections::HashMap;
+
+/// Where a tracked transaction stands, as far as the headers tell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionStatus {
+    /// No valid proof of it was received yet.
+    Unconfirmed,
+    /// Proven to be in the block `block_hash` at `height`, with
+    /// `confirmations` blocks counting it, 1 when it is the tip.
+    Confirmed {
+        block_hash: Hash,
+        height: u64,
+        confirmations: u64,
+    },
+}
+
+/// A light client (SPV, simplified payment verification): it follows the
+/// chain by its headers only and checks merkle proofs that the
+/// transactions it cares about are in a block, without the UTXO set.
+///
+/// Like any SPV client, it trusts that the chain with the most work only
+/// has valid transactions, and a proven transaction may still be
+/// double-spent in a block it hasn't seen.
+#[derive(Clone, Debug)]
+pub struct SpvClient {
+    headers: HeaderChain,
+    // tracked transactions by txid, with the hash of the block proven to
+    // include them
+    transactions: HashMap<Hash, Option<Hash>>,
+}
+
+impl SpvClient {
+    pub fn new(params: ChainParams) -> Self {
+        SpvClient {
+            headers: HeaderChain::new(params),
+            transactions: HashMap::new(),
+        }
+    }
+
+    pub fn headers(&self) -> &HeaderChain {
+        &self.headers
+    }
+
+    /// Extend the header chain, see `HeaderChain::add_headers`.
+    pub fn add_headers(&mut self, headers: Vec<BlockHeader>) -> Result<()> {
+        self.headers.add_headers(headers)
+    }
+
+    /// Start tracking the transaction `txid`, unconfirmed until a proof of
+    /// it is added.
+    pub fn track(&mut self, txid: Hash) {
+        self.transactions.entry(txid).or_insert(None);
+    }
+
+    /// Check a proof that `transaction` is in the block `block_hash`,
+    /// e.g. from `Blockchain::transaction_proof`, and track it as
+    /// confirmed there. Fails with `InvalidHash` if the block's header
+    /// isn't in the chain and `InvalidMerkleRoot` if the proof doesn't
+    /// match it.
+    pub fn add_proof(
+        &mut self,
+        block_hash: &Hash,
+        transaction: &Transaction,
+        proof: &MerkleProof,
+    ) -> Result<()> {
+        let header = self
+            .headers
+            .height_of(block_hash)
+            .and_then(|height| self.headers.header_at(height))
+            .ok_or(BtcError::InvalidHash)?;
+        if !proof.verify(transaction, &header.merkle_root) {
+            return Err(BtcError::InvalidMerkleRoot);
+        }
+        self.transactions
+            .insert(transaction.hash(), Some(*block_hash));
+        Ok(())
+    }
+
+    /// Check `transactions` against a merkle block, e.g. from
+    /// `Blockchain::merkle_block`, and track them as confirmed in it.
+    /// Fails with `InvalidHash` if the block's header isn't in the chain
+    /// and `InvalidMerkleRoot` if the tree doesn't match it or any of the
+    /// transactions isn't one of its matches.
+    pub fn add_merkle_block(
+        &mut self,
+        merkle_block: &MerkleBlock,
+        transactions: &[Transaction],
+    ) -> Result<()> {
+        let block_hash = merkle_block.header.hash();
+        // the header must be the one in the chain, not just any header
+        // with valid proof of work
+        if self.headers.height_of(&block_hash).is_none() {
+            return Err(BtcError::InvalidHash);
+        }
+        let matches =
+            merkle_block.matches().ok_or(BtcError::InvalidMerkleRoot)?;
+        for transaction in transactions {
+            let leaf = util::merkle_leaf(transaction);
+            if !matches.iter().any(|(_, hash)| *hash == leaf) {
+                return Err(BtcError::InvalidMerkleRoot);
+            }
+        }
+        for transaction in transactions {
+            self.transactions
+                .insert(transaction.hash(), Some(block_hash));
+        }
+        Ok(())
+    }
+
+    /// Status of the tracked transaction `txid`, None if it isn't tracked.
+    pub fn status(&self, txid: &Hash) -> Option<TransactionStatus> {
+        let Some(block_hash) = self.transactions.get(txid)? else {
+            return Some(TransactionStatus::Unconfirmed);
+        };
+        // proofs are only accepted for blocks in the chain
+        let height = self.headers.height_of(block_hash)?;
+        Some(TransactionStatus::Confirmed {
+            block_hash: *block_hash,
+            height,
+            confirmations: self.headers.len() - height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+    use crate::test_util::{self, output, spend};
+    use crate::types::Blockchain;
+
+    // A full chain whose second block holds a transaction, returned too.
+    fn chain_with_transaction() -> (Blockchain, Transaction) {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        let block = test_util::next_block(
+            &chain,
+            vec![transaction.clone()],
+            &key.public_key(),
+        );
+        chain.add_block(block).unwrap();
+        (chain, transaction)
+    }
+
+    fn headers(chain: &Blockchain, from: usize) -> Vec<BlockHeader> {
+        chain.blocks[from..]
+            .iter()
+            .map(|block| block.header.clone())
+            .collect()
+    }
+
+    #[test]
+    fn proven_transactions_gain_confirmations_with_the_headers() {
+        let (mut chain, transaction) = chain_with_transaction();
+        let txid = transaction.hash();
+        let mut client = SpvClient::new(test_util::params());
+        client.add_headers(headers(&chain, 0)).unwrap();
+        assert_eq!(client.status(&txid), None);
+        client.track(txid);
+        assert_eq!(client.status(&txid), Some(TransactionStatus::Unconfirmed));
+
+        let (block_hash, proof) = chain.transaction_proof(&txid).unwrap();
+        // a proof for another transaction or block is rejected
+        let coinbase = &chain.blocks[1].transactions[0];
+        assert!(matches!(
+            client.add_proof(&block_hash, coinbase, &proof),
+            Err(BtcError::InvalidMerkleRoot)
+        ));
+        assert!(matches!(
+            client.add_proof(&Hash::zero(), &transaction, &proof),
+            Err(BtcError::InvalidHash)
+        ));
+        client.add_proof(&block_hash, &transaction, &proof).unwrap();
+        let confirmed = |confirmations| TransactionStatus::Confirmed {
+            block_hash,
+            height: 1,
+            confirmations,
+        };
+        assert_eq!(client.status(&txid), Some(confirmed(1)));
+
+        let miner = PrivateKey::new_key().public_key();
+        test_util::mine_blocks(&mut chain, 2, &miner);
+        client.add_headers(headers(&chain, 2)).unwrap();
+        assert_eq!(client.status(&txid), Some(confirmed(3)));
+    }
+
+    #[test]
+    fn merkle_blocks_confirm_only_their_matches() {
+        let (chain, transaction) = chain_with_transaction();
+        let txid = transaction.hash();
+        let mut client = SpvClient::new(test_util::params());
+        client.add_headers(headers(&chain, 0)).unwrap();
+        client.track(txid);
+
+        let merkle_block = chain.merkle_block(1, &[txid]).unwrap();
+        let coinbase = chain.blocks[1].transactions[0].clone();
+        assert!(matches!(
+            client.add_merkle_block(&merkle_block, &[coinbase]),
+            Err(BtcError::InvalidMerkleRoot)
+        ));
+        let mut forged = merkle_block.clone();
+        forged.header = chain.blocks[0].header.clone();
+        assert!(client
+            .add_merkle_block(&forged, std::slice::from_ref(&transaction))
+            .is_err());
+        client
+            .add_merkle_block(&merkle_block, &[transaction])
+            .unwrap();
+        assert!(matches!(
+            client.status(&txid),
+            Some(TransactionStatus::Confirmed { height: 1, .. })
+        ));
+    }
+}
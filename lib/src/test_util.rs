@@ -0,0 +1,140 @@
+//! Helpers building small regtest chains for the unit tests.
+use crate::amount::Amount;
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::events::ChainListener;
+use crate::params::ChainParams;
+use crate::sha256::Hash;
+use crate::types::{
+    Block, BlockHeader, Blockchain, Outpoint, Transaction, TransactionInput,
+    TransactionOutput,
+};
+use crate::util::MerkleRoot;
+use chrono::Duration;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Regtest parameters where a coinbase can be spent by the next block.
+pub fn params() -> ChainParams {
+    ChainParams {
+        coinbase_maturity: 1,
+        ..ChainParams::regtest()
+    }
+}
+
+/// A chain with `params()`, holding a genesis block paying `miner`.
+pub fn chain(miner: &PublicKey) -> Blockchain {
+    Blockchain::new_with_genesis(params(), miner.clone()).unwrap()
+}
+
+pub fn output(sats: u64, pubkey: &PublicKey) -> TransactionOutput {
+    TransactionOutput {
+        value: Amount::from_sats(sats),
+        unique_id: Uuid::new_v4(),
+        pubkey: pubkey.clone(),
+    }
+}
+
+/// The block extending `chain` with `transactions`, 10 seconds after the
+/// tip, with a coinbase paying `miner` the reward and the fees.
+pub fn next_block(
+    chain: &Blockchain,
+    transactions: Vec<Transaction>,
+    miner: &PublicKey,
+) -> Block {
+    let height = chain.block_height();
+    let tip = chain.blocks.last().unwrap();
+    let fees = transactions
+        .iter()
+        .filter_map(|transaction| transaction.fee(&chain.utxos).ok());
+    let value = Amount::checked_sum(fees)
+        .and_then(|fees| chain.params().block_reward(height).checked_add(fees))
+        .unwrap();
+    let mut all = vec![Transaction::coinbase(
+        height,
+        vec![output(value.to_sats(), miner)],
+    )];
+    all.extend(transactions);
+    let mut header = BlockHeader::new(
+        tip.header.timestamp + Duration::seconds(10),
+        0,
+        tip.hash(),
+        MerkleRoot::calculate(&all),
+        chain.next_target(),
+    );
+    assert!(header.mine(u64::MAX));
+    Block::new(header, all)
+}
+
+/// Add `count` blocks paying `miner` to `chain`.
+pub fn mine_blocks(chain: &mut Blockchain, count: usize, miner: &PublicKey) {
+    for _ in 0..count {
+        let block = next_block(chain, vec![], miner);
+        chain.add_block(block).unwrap();
+    }
+}
+
+/// A transaction spending `inputs` (outpoint and value), all owned by
+/// `key`, into `outputs`.
+pub fn spend(
+    key: &PrivateKey,
+    inputs: &[(Outpoint, Amount)],
+    outputs: Vec<TransactionOutput>,
+) -> Transaction {
+    let placeholder = Signature::sign_output(&Hash::zero(), key);
+    let mut transaction = Transaction::new(
+        inputs
+            .iter()
+            .map(|(outpoint, _)| {
+                TransactionInput::new(*outpoint, placeholder.clone())
+            })
+            .collect(),
+        outputs,
+    );
+    for (index, (_, value)) in inputs.iter().enumerate() {
+        transaction.sign_input(index, *value, key).unwrap();
+    }
+    transaction
+}
+
+/// The first output of the coinbase of `block`, with its value.
+pub fn coinbase_output(block: &Block) -> (Outpoint, Amount) {
+    let coinbase = &block.transactions[0];
+    (Outpoint::new(coinbase.hash(), 0), coinbase.outputs[0].value)
+}
+
+/// A path in the temporary directory no other test uses.
+pub fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("btc-test-{}", Uuid::new_v4()))
+}
+
+/// Event seen by a `Recorder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Connected(u64, Hash),
+    Disconnected(u64, Hash),
+}
+
+/// Listener recording the blocks connected and disconnected, in order.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub events: Mutex<Vec<Event>>,
+}
+
+impl Recorder {
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut self.events.lock().unwrap())
+    }
+}
+
+impl ChainListener for Recorder {
+    fn on_block_connected(&self, block: &Block, height: u64) {
+        let event = Event::Connected(height, block.hash());
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn on_block_disconnected(&self, block: &Block, height: u64) {
+        let event = Event::Disconnected(height, block.hash());
+        self.events.lock().unwrap().push(event);
+    }
+}
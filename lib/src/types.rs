@@ -1,345 +1,5557 @@
-use crate::crypto::{PublicKey, Signature};
+use crate::amount::Amount;
+use crate::clock::{Clock, SystemClock};
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::decode;
 use crate::error::{BtcError, Result};
+use crate::events::ChainListener;
+use crate::headers;
+use crate::mempool::Mempool;
+use crate::params::{ChainParams, Deployment, DeploymentState};
+use crate::policy::StandardnessPolicy;
 use crate::sha256::Hash;
-use crate::util::MerkleRoot;
+use crate::util::{self, MerkleProof, MerkleRoot, PartialMerkleTree};
+use crate::utxo::{BlockUndo, Coin, UtxoSet};
+use crate::validation::ValidationReport;
 use crate::U256;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::u64;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
+
+// Version of the sighash preimage layout, see
+// `Transaction::sighash_preimage`.
+const SIGHASH_VERSION: u32 = 2;
 /// Blockchain is a chain of blocks
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Blockchain {
     /// A blockchain is a chain of blocks
     //a naive implementation would be a vector of blocks.
     pub blocks: Vec<Block>,
-    pub utxos: HashMap<Hash, TransactionOutput>,
+    pub utxos: UtxoSet,
+    // how often (in blocks) and where add_block saves the chain, not saved
+    // with it
+    #[serde(skip)]
+    autocheckpoint: Option<(u64, PathBuf)>,
+    // blocks of competing branches, keyed by hash, including the ones
+    // disconnected by a reorganization
+    #[serde(default)]
+    side_blocks: HashMap<Hash, Block>,
+    // outputs spent by each block of the chain, by block hash, to
+    // disconnect it
+    #[serde(default)]
+    undo: HashMap<Hash, BlockUndo>,
+    // txid of every transaction of the chain, kept when pruning, so the
+    // same transaction can't be included twice
+    #[serde(default)]
+    txids: HashSet<Hash>,
+    // total work of the chain ending at each known block, active or side,
+    // rebuilt from the headers when loading
+    #[serde(skip)]
+    chain_work: HashMap<Hash, U256>,
+    // side blocks that failed validation when connected, the blocks
+    // building on them are invalid too
+    #[serde(default)]
+    invalid_blocks: HashSet<Hash>,
+    // number of blocks at the end of the chain imported by import_headers
+    // whose transactions aren't applied to the UTXO set yet
+    #[serde(default)]
+    unconnected: u64,
+    // blocks whose parent is unknown yet, with when they arrived, not saved
+    // with the chain
+    #[serde(skip)]
+    orphans: HashMap<Hash, (Block, DateTime<Utc>)>,
+    // consensus parameters, chains saved before they existed are mainnet
+    #[serde(default)]
+    params: ChainParams,
+    // where the current time comes from, not saved with the chain
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<dyn Clock>,
+    // notified of the blocks connected and disconnected, not saved with
+    // the chain
+    #[serde(skip)]
+    listeners: Vec<Arc<dyn ChainListener>>,
+}
+
+fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
 }
 
 impl Blockchain {
     /// Constructor for the Blockchain type, by default it will be empty.
     pub fn new() -> Self {
+        Self::with_params(ChainParams::mainnet())
+    }
+
+    /// An empty chain following the given consensus parameters, e.g.
+    /// `ChainParams::regtest()` for experiments.
+    pub fn with_params(params: ChainParams) -> Self {
         Blockchain {
             blocks: vec![],
-            utxos: HashMap::new(),
+            utxos: UtxoSet::new(),
+            undo: HashMap::new(),
+            txids: HashSet::new(),
+            chain_work: HashMap::new(),
+            invalid_blocks: HashSet::new(),
+            unconnected: 0,
+            autocheckpoint: None,
+            side_blocks: HashMap::new(),
+            orphans: HashMap::new(),
+            params,
+            clock: system_clock(),
+            listeners: vec![],
         }
     }
+
+    /// A chain with the given parameters, starting with the block built by
+    /// `Block::genesis` and its coinbase output as the only UTXO.
+    ///
+    /// Fails if the parameters reject their own genesis block, e.g. with a
+    /// checkpoint at height 0 or a `genesis_timestamp` too far in the
+    /// future.
+    pub fn new_with_genesis(
+        params: ChainParams,
+        coinbase_pubkey: PublicKey,
+    ) -> Result<Self> {
+        let genesis = Block::genesis(&params, coinbase_pubkey)?;
+        let mut blockchain = Self::with_params(params);
+        blockchain.add_block(genesis)?;
+        Ok(blockchain)
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Replace the system clock used to reject blocks too far in the
+    /// future, e.g. with a `FixedClock` in tests. Like the autocheckpoint,
+    /// it isn't saved with the chain.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // Whether `timestamp` is too far ahead of the clock for a block.
+    fn is_too_far_in_future(&self, timestamp: DateTime<Utc>) -> bool {
+        timestamp
+            > self.clock.now()
+                + chrono::Duration::seconds(self.params.max_future_block_time)
+    }
     //As we are using a vector we added the block to the end of the vector.
     /// Add a block to the blockchain.
+    ///
+    /// A block building on an earlier block than the tip, or on a block of
+    /// a competing branch, is kept aside. Once its branch has more work than
+    /// the active chain, the blocks after the fork are disconnected and the
+    /// branch connected in their place, rolling the UTXO set back and
+    /// forward.
+    ///
+    /// Every block must pass `Block::check` first, and a block extending
+    /// the tip `contextual_check` too.
+    ///
+    /// A block whose parent is unknown is kept as an orphan and
+    /// `BtcError::OrphanBlock` returned. It is added automatically once its
+    /// parent is, unless it expired after `ORPHAN_EXPIRY` seconds or was
+    /// evicted to keep at most `MAX_ORPHAN_BLOCKS`.
+    ///
+    /// No block can be added while headers imported with `import_headers`
+    /// are waiting for their body.
     pub fn add_block(&mut self, block: Block) -> Result<()> {
-        //check if the blockchain is empty
-        if self.blocks.is_empty() {
-            //if this is the first block check if the
-            //block's prev_block hash is all zeros
-            if block.header.prev_block_hash != Hash::zero() {
-                print!("zero hash");
-                return Err(BtcError::InvalidBlock);
-            } else {
-                // if this is not the first block, check if the
-                // block's prev_block_hash is the hash of the last block
-                let last_block = self.blocks.last().unwrap();
-                if block.header.prev_block_hash != last_block.hash() {
-                    println!("prev hash is wrong");
-                    return Err(BtcError::InvalidBlock);
-                }
-                if !block.header.hash().matches_target(block.header.target) {
-                    println!("does not match target");
-                    return Err(BtcError::InvalidBlock);
-                }
-
-                //check of the block's merkle root is correct
-                let callculated_merkle_root =
-                    MerkleRoot::calculate(&block.transactions);
-                if callculated_merkle_root != block.header.merkle_root {
-                    println!("invalid merkle root");
-                    return Err(BtcError::InvalidMerkleRoot);
-                }
-                // check if the block's timestamp is after the
-                // last block's timestamp
-                if block.header.timestamp <= last_block.header.timestamp {
-                    println!("invalid block timestamp");
-                    return Err(BtcError::InvalidBlock);
-                }
-                //Verify all transactions in the block
-                block.verify_transactions(self.block_height(), &self.utxos)?;
+        if self.unconnected > 0 {
+            println!("block bodies are missing");
+            return Err(BtcError::InvalidBlock);
+        }
+        if let Err(e) = block.check() {
+            println!("block failed the sanity checks");
+            return Err(e);
+        }
+        // a block not building on the tip may belong to a competing branch
+        if let Some(last_block) = self.blocks.last() {
+            if block.header.prev_block_hash != last_block.hash() {
+                return self.add_side_block(block);
             }
         }
+        self.contextual_check(&block)?;
+        let hash = block.hash();
+        let undo = self.utxos.apply_block(&block, self.block_height());
+        self.undo.insert(hash, undo);
+        self.txids
+            .extend(block.transactions.iter().map(Transaction::hash));
+        self.record_chain_work(&block.header);
         self.blocks.push(block);
+        self.notify_connected(self.block_height() - 1);
+        self.checkpoint_if_due();
+        self.connect_orphans(hash);
         Ok(())
     }
 
-    //Rebuild UTXO set from the blockchain
-    pub fn rebuild_utxos(&mut self) {
-        for block in &self.blocks {
+    /// Check `block` against the chain it would extend: it must build on
+    /// the tip (or be a genesis block, building on the zero hash), have the
+    /// expected target, a timestamp after the median time past and not too
+    /// far in the future, and transactions valid against the UTXO set. The
+    /// context-free checks are `Block::check`'s.
+    pub fn contextual_check(&self, block: &Block) -> Result<()> {
+        // otherwise everyone would have to wait for that time to extend it
+        if self.is_too_far_in_future(block.header.timestamp) {
+            println!("block timestamp too far in the future");
+            return Err(BtcError::InvalidBlock);
+        }
+        // the genesis block has no previous block to be checked against,
+        // but its prev_block hash must be all zeros
+        let expected_prev_hash = self
+            .blocks
+            .last()
+            .map_or(Hash::zero(), |block| block.hash());
+        if block.header.prev_block_hash != expected_prev_hash {
+            println!("block doesn't build on the tip");
+            return Err(BtcError::InvalidBlock);
+        }
+        if !self
+            .params
+            .matches_checkpoint(self.block_height(), &block.hash())
+        {
+            println!("block contradicts a checkpoint");
+            return Err(BtcError::InvalidBlock);
+        }
+        // the block's timestamp must be after the median time past rather
+        // than the last block's, so one block with a timestamp far ahead
+        // can't stop the others from following it
+        if block.header.timestamp <= self.median_time_past() {
+            println!("invalid block timestamp");
+            return Err(BtcError::InvalidBlock);
+        }
+        // the target is set by the chain, not by the miner
+        if block.header.target != self.next_target() {
+            println!("wrong target");
+            return Err(BtcError::InvalidBlock);
+        }
+        // transactions can't be included before their lock time
+        let median_time_past = self.median_time_past();
+        if block.transactions.iter().any(|transaction| {
+            !transaction.is_final(self.block_height(), median_time_past)
+        }) {
+            println!("non-final transaction");
+            return Err(BtcError::InvalidTransaction);
+        }
+        // coinbase outputs can't be spent before they mature
+        if let Err(e) =
+            self.verify_coinbase_spends(self.block_height(), block, &self.utxos)
+        {
+            println!("immature coinbase spend");
+            return Err(e);
+        }
+        // nor can inputs be spent before their relative lock time
+        if let Err(e) =
+            self.verify_sequence_locks(self.block_height(), block, &self.utxos)
+        {
+            println!("sequence lock not met");
+            return Err(e);
+        }
+        if self.has_duplicate_transactions(block) {
+            println!("duplicate transaction");
+            return Err(BtcError::InvalidTransaction);
+        }
+        //Verify all transactions in the block, including the coinbase
+        if self.assumes_valid(self.block_height(), block) {
+            block.verify_transactions_without_signatures(
+                self.block_height(),
+                &self.utxos,
+                &self.params,
+            )
+        } else {
+            block.verify_transactions(
+                self.block_height(),
+                &self.utxos,
+                &self.params,
+            )
+        }
+    }
+
+    // Whether the signatures of `block`, at `height`, are trusted: it is
+    // the `assume_valid` block, or one of its ancestors in the chain, which
+    // then already has the assumed block, e.g. from `import_headers`. A
+    // block of any other branch has its signatures verified, whatever its
+    // height.
+    fn assumes_valid(&self, height: u64, block: &Block) -> bool {
+        let Some((assumed_height, assumed_hash)) = self.params.assume_valid
+        else {
+            return false;
+        };
+        let hash = block.hash();
+        if height == assumed_height {
+            return hash == assumed_hash;
+        }
+        let in_chain = |height: u64, hash: Hash| {
+            self.blocks
+                .get(height as usize)
+                .is_some_and(|block| block.hash() == hash)
+        };
+        height < assumed_height
+            && in_chain(assumed_height, assumed_hash)
+            && in_chain(height, hash)
+    }
+
+    // Whether `block` includes a transaction already in the chain or
+    // earlier in the block, which isn't allowed even if it spends nothing.
+    fn has_duplicate_transactions(&self, block: &Block) -> bool {
+        let mut txids = HashSet::new();
+        block.transactions.iter().any(|transaction| {
+            let txid = transaction.hash();
+            self.txids.contains(&txid) || !txids.insert(txid)
+        })
+    }
+
+    /// Register a listener notified of every block connected to or
+    /// disconnected from the chain from now on. Like the clock, listeners
+    /// aren't saved with the chain.
+    pub fn add_listener(&mut self, listener: Arc<dyn ChainListener>) {
+        self.listeners.push(listener);
+    }
+
+    // Tell the listeners about the block at `height` and its transactions.
+    fn notify_connected(&self, height: u64) {
+        let block = &self.blocks[height as usize];
+        for listener in &self.listeners {
+            listener.on_block_connected(block, height);
             for transaction in &block.transactions {
-                // If a transaction output is used as input, the
-                // output must be removed from the UTXO set
-                for input in &transaction.inputs {
-                    self.utxos.remove(&input.prev_transaction_output_hash);
+                listener.on_transaction_added(transaction, height);
+            }
+        }
+    }
+
+    /// Number of blocks waiting for their parent, see `add_block`.
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    // Keep a block whose parent is unknown until it arrives, making room
+    // by dropping the expired orphans and then the oldest ones.
+    fn add_orphan(&mut self, block: Block) {
+        let now = self.clock.now();
+        let expiry = now - chrono::Duration::seconds(crate::ORPHAN_EXPIRY);
+        self.orphans.retain(|_, (_, received)| *received > expiry);
+        while self.orphans.len() >= crate::MAX_ORPHAN_BLOCKS {
+            let Some(oldest) = self
+                .orphans
+                .iter()
+                .min_by_key(|(_, (_, received))| *received)
+                .map(|(hash, _)| *hash)
+            else {
+                break;
+            };
+            self.orphans.remove(&oldest);
+        }
+        self.orphans.insert(block.hash(), (block, now));
+    }
+
+    // Add the orphans waiting for the block `parent`, which in turn adds
+    // the ones waiting for them. Invalid orphans are dropped.
+    fn connect_orphans(&mut self, parent: Hash) {
+        let children: Vec<Hash> = self
+            .orphans
+            .iter()
+            .filter(|(_, (block, _))| block.header.prev_block_hash == parent)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in children {
+            if let Some((block, _)) = self.orphans.remove(&hash) {
+                let _ = self.add_block(block);
+            }
+        }
+    }
+
+    // Save the chain if an autocheckpoint is set and due at this height.
+    fn checkpoint_if_due(&self) {
+        if let Some((every, path)) = &self.autocheckpoint {
+            if self.block_height().is_multiple_of(*every) {
+                // the block is already added, so a failed checkpoint is
+                // reported but doesn't reject it
+                if let Err(e) = self.save_to_file(path) {
+                    println!("failed to write checkpoint: {}", e);
                 }
-                // add all new transactions outputs to the UTXO set
-                for output in transaction.outputs.iter() {
-                    self.utxos.insert(transaction.hash(), output.clone());
+            }
+        }
+    }
+
+    // Keep a block that doesn't extend the tip in the side blocks, and
+    // reorganize onto its branch if that now has the most work. Only the
+    // checks that don't depend on the branch are done here, the others when
+    // the block gets connected.
+    fn add_side_block(&mut self, block: Block) -> Result<()> {
+        if self.is_too_far_in_future(block.header.timestamp) {
+            println!("block timestamp too far in the future");
+            return Err(BtcError::InvalidBlock);
+        }
+        let hash = block.hash();
+        if self.side_blocks.contains_key(&hash)
+            || self.blocks.iter().any(|known| known.hash() == hash)
+        {
+            println!("block already known");
+            return Err(BtcError::InvalidBlock);
+        }
+        self.side_blocks.insert(hash, block);
+        let Some((fork_height, branch)) = self.branch_of(hash) else {
+            // the parent may still arrive
+            if let Some(block) = self.side_blocks.remove(&hash) {
+                self.add_orphan(block);
+            }
+            println!("orphan block");
+            return Err(BtcError::OrphanBlock);
+        };
+        // the branch can't replace a checkpointed block of the active
+        // chain, nor have a different block at a checkpoint height
+        let height = (fork_height + branch.len() - 1) as u64;
+        let replaces_checkpoint = self
+            .params
+            .checkpoints
+            .range(fork_height as u64..self.block_height())
+            .next()
+            .is_some();
+        if replaces_checkpoint || !self.params.matches_checkpoint(height, &hash)
+        {
+            self.side_blocks.remove(&hash);
+            println!("block contradicts a checkpoint");
+            return Err(BtcError::InvalidBlock);
+        }
+        let header = self.side_blocks[&hash].header.clone();
+        self.record_chain_work(&header);
+        // kept to be reported by chain_tips, but never connected
+        if branch.iter().any(|hash| self.invalid_blocks.contains(hash)) {
+            println!("block builds on an invalid block");
+            return Err(BtcError::InvalidBlock);
+        }
+        // on a tie the branch seen first stays active
+        if self.chain_work[&hash] > self.total_work() {
+            self.reorganize(fork_height, &branch)?;
+        }
+        self.connect_orphans(hash);
+        Ok(())
+    }
+
+    // Walk back from the side block `hash` to the active chain. Returns the
+    // height the branch forks at, that is how many active blocks it shares,
+    // and its side blocks oldest first. None if it doesn't reach the chain.
+    fn branch_of(&self, hash: Hash) -> Option<(usize, Vec<Hash>)> {
+        let mut branch = vec![];
+        let mut current = hash;
+        while let Some(block) = self.side_blocks.get(&current) {
+            branch.push(current);
+            current = block.header.prev_block_hash;
+        }
+        branch.reverse();
+        let fork_height = self
+            .blocks
+            .iter()
+            .position(|block| block.hash() == current)?
+            + 1;
+        Some((fork_height, branch))
+    }
+
+    // Make the side blocks `branch`, which fork from the active chain after
+    // its first `fork_height` blocks, the new active chain. The blocks after
+    // the fork are disconnected using their undo data and kept as side
+    // blocks, then the branch blocks are connected, fully validated like
+    // `add_block` does.
+    //
+    // If a branch block is invalid, only that block is marked as such and
+    // the chain is left untouched. The blocks building on it are rejected
+    // as invalid too, as their branch contains it (see `add_side_block`).
+    // Blocks without undo data, like pruned ones, can't be disconnected,
+    // so the chain can't reorganize past them. Transactions of disconnected blocks aren't
+    // returned to any mempool.
+    fn reorganize(
+        &mut self,
+        fork_height: usize,
+        branch: &[Hash],
+    ) -> Result<()> {
+        // work on a copy, so the chain is untouched if anything fails
+        let mut candidate = Blockchain {
+            blocks: self.blocks.clone(),
+            utxos: self.utxos.clone(),
+            undo: self.undo.clone(),
+            txids: self.txids.clone(),
+            chain_work: self.chain_work.clone(),
+            invalid_blocks: HashSet::new(),
+            unconnected: 0,
+            autocheckpoint: None,
+            side_blocks: HashMap::new(),
+            orphans: HashMap::new(),
+            params: self.params.clone(),
+            clock: self.clock.clone(),
+            listeners: vec![],
+        };
+        let mut disconnected = vec![];
+        while candidate.blocks.len() > fork_height {
+            match candidate.disconnect_tip() {
+                Ok(block) => disconnected.push(block),
+                Err(e) => {
+                    println!("can't disconnect a block without undo data");
+                    return Err(e);
                 }
             }
         }
+        for hash in branch {
+            if let Err(e) = candidate.add_block(self.side_blocks[hash].clone())
+            {
+                self.invalid_blocks.insert(*hash);
+                return Err(e);
+            }
+        }
+        for hash in branch {
+            self.side_blocks.remove(hash);
+        }
+        self.blocks = candidate.blocks;
+        self.utxos = candidate.utxos;
+        self.undo = candidate.undo;
+        self.txids = candidate.txids;
+        // disconnected is tip first
+        for (depth, block) in disconnected.iter().enumerate() {
+            let height = (fork_height + disconnected.len() - 1 - depth) as u64;
+            for listener in &self.listeners {
+                listener.on_block_disconnected(block, height);
+            }
+        }
+        for height in fork_height..self.blocks.len() {
+            self.notify_connected(height as u64);
+        }
+        for block in disconnected {
+            self.side_blocks.insert(block.hash(), block);
+        }
+        self.checkpoint_if_due();
+        Ok(())
+    }
+
+    // Record the total work of the chain ending at `header`, whose parent
+    // must be known unless it is the genesis block.
+    fn record_chain_work(&mut self, header: &BlockHeader) {
+        let parent_work = self
+            .chain_work
+            .get(&header.prev_block_hash)
+            .copied()
+            .unwrap_or_default();
+        self.chain_work
+            .insert(header.hash(), parent_work.saturating_add(header.work()));
+    }
+
+    // Recompute the work of every block of the chain and of the side
+    // branches, as it isn't saved.
+    fn rebuild_chain_work(&mut self) {
+        self.chain_work.clear();
+        let headers: Vec<BlockHeader> = self
+            .blocks
+            .iter()
+            .map(|block| block.header.clone())
+            .collect();
+        for header in &headers {
+            self.record_chain_work(header);
+        }
+        // a side block needs its parent's work first, which may be another
+        // side block
+        let mut pending: Vec<BlockHeader> = self
+            .side_blocks
+            .values()
+            .map(|block| block.header.clone())
+            .collect();
+        loop {
+            let (ready, waiting): (Vec<BlockHeader>, Vec<BlockHeader>) =
+                pending.into_iter().partition(|header| {
+                    self.chain_work.contains_key(&header.prev_block_hash)
+                });
+            if ready.is_empty() {
+                break;
+            }
+            for header in &ready {
+                self.record_chain_work(header);
+            }
+            pending = waiting;
+        }
+    }
+
+    /// Make `add_block` save the chain to `path` every `every` blocks, so
+    /// `load_from_file` can resume from the latest checkpoint after a
+    /// crash. Each checkpoint replaces the previous one; 0 disables them.
+    /// The setting itself isn't saved, it must be set again after loading.
+    pub fn set_autocheckpoint(&mut self, every: u64, path: PathBuf) {
+        self.autocheckpoint = Some((every, path));
+    }
+
+    /// Write the whole chain, blocks and UTXO set, to `path`. The data is
+    /// written to a temporary file first and then renamed over `path`, so a
+    /// crash while saving leaves the previous file intact.
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        ciborium::into_writer(self, &mut writer).map_err(|e| match e {
+            ciborium::ser::Error::Io(e) => BtcError::Io(e),
+            ciborium::ser::Error::Value(_) => BtcError::Serialization,
+        })?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Read a chain written by `save_to_file`.
+    ///
+    /// The UTXO set is rebuilt from the blocks rather than trusted from the
+    /// file, unless the chain was pruned: the blocks can't be replayed then,
+    /// so the stored set is kept.
+    pub fn load_from_file(path: &Path) -> Result<Blockchain> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut blockchain: Blockchain = ciborium::from_reader(reader)
+            .map_err(|e| match e {
+                ciborium::de::Error::Io(e) => BtcError::Io(e),
+                _ => BtcError::Serialization,
+            })?;
+        // a valid block always has a coinbase, so no transactions means
+        // it was pruned
+        if blockchain
+            .blocks
+            .iter()
+            .all(|block| !block.transactions.is_empty())
+        {
+            blockchain.rebuild_utxos();
+        }
+        blockchain.rebuild_chain_work();
+        Ok(blockchain)
+    }
+
+    //Rebuild UTXO set from the blockchain
+    pub fn rebuild_utxos(&mut self) {
+        // start from scratch, outputs spent in the blocks must not survive
+        self.utxos = UtxoSet::new();
+        self.undo.clear();
+        self.txids.clear();
+        // bodies after the imported headers are only applied once the
+        // ones before them are there
+        let connected = self.blocks.len() - self.unconnected as usize;
+        for (height, block) in self.blocks[..connected].iter().enumerate() {
+            let undo = self.utxos.apply_block(block, height as u64);
+            self.undo.insert(block.hash(), undo);
+            self.txids
+                .extend(block.transactions.iter().map(Transaction::hash));
+        }
+    }
+    /// Remove the last block of the chain and return it, putting the UTXO
+    /// set back as it was before the block: the outputs it created are
+    /// removed and the ones it spent restored from its undo data. Fails
+    /// on an empty chain, or if the block has no undo data because it was
+    /// pruned or only its header was imported so far.
+    pub fn disconnect_tip(&mut self) -> Result<Block> {
+        let hash = self.blocks.last().ok_or(BtcError::InvalidBlock)?.hash();
+        let undo = self.undo.remove(&hash).ok_or(BtcError::MissingUndoData)?;
+        let block = self.blocks.pop().unwrap();
+        self.utxos.undo_block(&block, &undo);
+        for transaction in &block.transactions {
+            self.txids.remove(&transaction.hash());
+        }
+        self.chain_work.remove(&hash);
+        for listener in &self.listeners {
+            listener.on_block_disconnected(&block, self.block_height());
+        }
+        Ok(block)
+    }
+
+    /// Whether the transaction `txid` is in a block of the chain, pruned
+    /// ones included.
+    pub fn contains_transaction(&self, txid: &Hash) -> bool {
+        self.txids.contains(txid)
+    }
+
+    /// The blocks of the active chain, from the genesis block.
+    pub fn iter_blocks(&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
+    /// Every transaction of the active chain with the height of its block,
+    /// in chain order. Pruned blocks have none.
+    pub fn iter_transactions(
+        &self,
+    ) -> impl Iterator<Item = (u64, &Transaction)> + '_ {
+        self.blocks.iter().enumerate().flat_map(|(height, block)| {
+            block
+                .transactions
+                .iter()
+                .map(move |transaction| (height as u64, transaction))
+        })
+    }
+
+    /// The unspent outputs paying `pubkey`, in no particular order.
+    pub fn iter_utxos_for_pubkey<'a>(
+        &'a self,
+        pubkey: &'a PublicKey,
+    ) -> impl Iterator<Item = (&'a Outpoint, &'a TransactionOutput)> + 'a {
+        self.utxos
+            .iter()
+            .filter(move |(_, output)| output.pubkey == *pubkey)
+    }
+
+    /// The transaction `txid` with the height of its block. None if it
+    /// isn't in the chain or only in a pruned block.
+    pub fn find_transaction(&self, txid: &Hash) -> Option<(u64, &Transaction)> {
+        self.iter_transactions()
+            .find(|(_, transaction)| transaction.hash() == *txid)
     }
     pub fn block_height(&self) -> u64 {
         self.blocks.len() as u64
     }
-}
 
-/// A block is a collection of transactions with a header.
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Block {
-    /// The header of the block
-    pub header: BlockHeader,
-    /// The transactions in the block
-    pub transactions: Vec<Transaction>,
-}
-impl Block {
-    pub fn new(header: BlockHeader, transactions: Vec<Transaction>) -> Self {
-        Block {
-            // in the new function if the parameter has the same name
-            // as the field you can write just once instead of field
-            // Block{header:header, transaction: transaction}
-            header,
-            transactions,
+    /// Drop the transactions of all but the last `keep` blocks to save
+    /// space. Headers are kept, and together with the UTXO set they are all
+    /// `add_block` needs to validate new blocks. Anything replaying old
+    /// transactions (`rebuild_utxos`, `utxos_at_height`, `validate_timed`,
+    /// `block_bundle`, `historical_fee`) won't work past the pruned blocks.
+    pub fn prune(&mut self, keep: u64) {
+        // the bodies not applied to the UTXO set yet are still needed
+        let connected = self.blocks.len() - self.unconnected as usize;
+        let prune_until = self
+            .blocks
+            .len()
+            .saturating_sub(keep as usize)
+            .min(connected);
+        for block in &mut self.blocks[..prune_until] {
+            block.transactions = vec![];
+            self.undo.remove(&block.hash());
         }
-        //this allows the function to be unimpemented but will crash at
-        //runtime
     }
-    pub fn hash(&self) -> Hash {
-        //this allows the function to be unimpemented but will crash at
-        //runtime
-        Hash::hash(self)
+
+    /// Validate the whole chain from genesis, like `add_block` would, and
+    /// report how long each phase took across all blocks. Stops at the
+    /// first invalid block; the timings then cover the blocks checked so far.
+    pub fn validate_timed(&self) -> (Result<()>, ValidationTimings) {
+        let mut timings = ValidationTimings::default();
+        let mut utxos = UtxoSet::new();
+        for (height, block) in self.blocks.iter().enumerate() {
+            let start = Instant::now();
+            let header_valid =
+                self.header_is_valid(height as u64, &block.header);
+            timings.headers += start.elapsed();
+            if !header_valid {
+                return (Err(BtcError::InvalidBlock), timings);
+            }
+
+            let start = Instant::now();
+            let merkle_result = block.verify_merkle_root();
+            timings.merkle += start.elapsed();
+            if let Err(e) = merkle_result {
+                return (Err(e), timings);
+            }
+
+            let start = Instant::now();
+            let transactions_result =
+                self.verify_body_at(height as u64, block, &utxos);
+            timings.signatures += start.elapsed();
+            if let Err(e) = transactions_result {
+                return (Err(e), timings);
+            }
+
+            utxos.apply_block(block, height as u64);
+        }
+        (Ok(()), timings)
     }
-    //Verify all transactions in the block
-    //A transactions must:
-    // - have the input from a UTXO
-    // - do not spend the same input twice in the block
-    // -  has a valid signature
-    // - has a output value less or equal than the input value
-    pub fn verify_transactions(
+
+    // Whether `header` can follow the first `height` blocks of the chain:
+    // it links to the previous block, has the expected target, a valid proof
+    // of work and a timestamp after the MTP and not too far in the future.
+    fn header_is_valid(&self, height: u64, header: &BlockHeader) -> bool {
+        let linked = match height.checked_sub(1) {
+            // the genesis block has no parent
+            None => header.prev_block_hash == Hash::zero(),
+            Some(prev_height) => {
+                let prev_block = &self.blocks[prev_height as usize];
+                header.prev_block_hash == prev_block.hash()
+            }
+        };
+        linked
+            && self.params.matches_checkpoint(height, &header.hash())
+            && header.target == self.next_target_at(height)
+            && header.hash().matches_target(header.target)
+            && header.timestamp > self.median_time_past_at(height)
+            && !self.is_too_far_in_future(header.timestamp)
+    }
+
+    // Check the transactions of the block at `height` against the UTXO set
+    // left by the blocks before it.
+    fn verify_body_at(
         &self,
-        predicted_block_height: u64,
-        utxos: &HashMap<Hash, TransactionOutput>,
+        height: u64,
+        block: &Block,
+        utxos: &UtxoSet,
     ) -> Result<()> {
-        let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
-        //reject completely empty blocks
-        if self.transactions.is_empty() {
-            return Err(BtcError::InvalidBlock);
+        let median_time_past = self.median_time_past_at(height);
+        if !block
+            .transactions
+            .iter()
+            .all(|transaction| transaction.is_final(height, median_time_past))
+        {
+            return Err(BtcError::InvalidTransaction);
         }
-        //verify coinbase transaction
-        self.verify_coinbase_transaction(predicted_block_height, utxos)?;
+        self.verify_coinbase_spends(height, block, utxos)?;
+        self.verify_sequence_locks(height, block, utxos)?;
+        if self.assumes_valid(height, block) {
+            block.verify_transactions_without_signatures(
+                height,
+                utxos,
+                &self.params,
+            )
+        } else {
+            block.verify_transactions(height, utxos, &self.params)
+        }
+    }
 
-        for transaction in &self.transactions {
-            let mut input_value = 0;
-            let mut output_value = 0;
-            for input in &transaction.inputs {
-                let prev_output =
-                    utxos.get(&input.prev_transaction_output_hash);
-                //If the transaction inputs does not come from an
-                //UTXO it is not valid
-                if prev_output.is_none() {
-                    return Err(BtcError::InvalidTransaction);
-                }
-                let prev_output = prev_output.unwrap();
-                //Prevents same-block double-spending, if a input is already in the
-                //inputs hash maps it means that a previous transaction in the same
-                //block comes from the same input
-                if inputs.contains_key(&input.prev_transaction_output_hash) {
-                    return Err(BtcError::InvalidTransaction);
-                }
+    // Check that the block at `height` only spends coinbase outputs that
+    // can be spent by then, see `ChainParams::coinbase_spendable_height`.
+    // The outputs are looked up in `utxos`, the set the block is validated
+    // against, so the blocks that created them aren't needed.
+    fn verify_coinbase_spends(
+        &self,
+        height: u64,
+        block: &Block,
+        utxos: &UtxoSet,
+    ) -> Result<()> {
+        for transaction in block.transactions.iter().skip(1) {
+            if !self.coinbase_spends_mature(height, transaction, utxos) {
+                return Err(BtcError::InvalidTransactionInput);
+            }
+        }
+        Ok(())
+    }
 
-                // check if the signature is valid
-                if !input.signature.verify(
-                    &input.prev_transaction_output_hash,
-                    &prev_output.pubkey,
-                ) {
-                    return Err(BtcError::InvalidSignature);
-                }
+    // Whether the coinbase outputs of `utxos` spent by `transaction` can
+    // be spent in a block at `height`.
+    fn coinbase_spends_mature(
+        &self,
+        height: u64,
+        transaction: &Transaction,
+        utxos: &UtxoSet,
+    ) -> bool {
+        transaction.inputs.iter().all(|input| {
+            utxos.coin(&input.prev_output).is_none_or(|coin| {
+                !coin.is_coinbase
+                    || height
+                        >= self.params.coinbase_spendable_height(coin.height)
+            })
+        })
+    }
 
-                // Update the inputs values
-                input_value += prev_output.value;
-                //add the input to the input hashmap
-                inputs.insert(
-                    input.prev_transaction_output_hash,
-                    prev_output.clone(),
-                );
-            }
-            //update the output value
-            for output in &transaction.outputs {
-                output_value += output.value;
-            }
-            // It is fine for output value to be less than input value
-            // as the difference is the fee for the miner
-            // But we must be sure that the output is the same or less
-            // than the input value
-            if input_value < output_value {
+    // Check the relative lock times of the transactions of the block at
+    // `height`, see `Transaction::sequence_locks_met`, with the heights
+    // the spent outputs were created at from `utxos`. Only headers are
+    // needed for the median time past, so this works on pruned chains.
+    fn verify_sequence_locks(
+        &self,
+        height: u64,
+        block: &Block,
+        utxos: &UtxoSet,
+    ) -> Result<()> {
+        for transaction in block.transactions.iter().skip(1) {
+            if !self.sequence_locks_met_at(height, transaction, utxos) {
                 return Err(BtcError::InvalidTransaction);
             }
         }
         Ok(())
     }
-    pub fn verify_coinbase_transaction(
+
+    // Whether the relative lock times of `transaction`, spending outputs
+    // of `utxos`, have passed in a block at `height`.
+    fn sequence_locks_met_at(
         &self,
-        predicted_block_height: u64,
-        utxos: &HashMap<Hash, TransactionOutput>,
-    ) -> Result<()> {
-        //Coinbase transaction is the first transation in the block
-        let coinbase_transaction = &self.transactions[0];
-        //The coinbase transaction generates new BTC
-        // it must not have any input
-        if coinbase_transaction.inputs.len() != 0 {
+        height: u64,
+        transaction: &Transaction,
+        utxos: &UtxoSet,
+    ) -> bool {
+        let origins: Vec<Option<(u64, DateTime<Utc>)>> = transaction
+            .inputs
+            .iter()
+            .map(|input| {
+                utxos.coin(&input.prev_output).map(|coin| {
+                    (coin.height, self.median_time_past_at(coin.height))
+                })
+            })
+            .collect();
+        transaction.sequence_locks_met(
+            height,
+            self.median_time_past_at(height),
+            &origins,
+        )
+    }
+
+    /// Check that `transaction` could be included in the next block, as
+    /// the mempool and the block template do before accepting it: it isn't
+    /// a coinbase nor already in the chain, it is final, only spends
+    /// mature coinbases, meets its relative lock times and is valid
+    /// against the UTXO set, signatures included. The limits on a whole
+    /// block aren't checked.
+    pub fn verify_transaction(&self, transaction: &Transaction) -> Result<()> {
+        let height = self.block_height();
+        if transaction.inputs.is_empty()
+            || self.txids.contains(&transaction.hash())
+        {
             return Err(BtcError::InvalidTransaction);
         }
-        //It must always generate new BTC, outputs can not be 0
-        if coinbase_transaction.outputs.len() == 0 {
+        if !transaction.is_final(height, self.median_time_past()) {
             return Err(BtcError::InvalidTransaction);
         }
-        //get the value of the whole block fee
-        let miner_fees = self.calculate_miner_fees(utxos)?;
-        //get the value of the expected new bitcoin minned
-        let block_reward = crate::INITIAL_REWARD * 10u64.pow(8)
-            / 2u64
-                .pow((predicted_block_height / crate::HALVING_INTERVAL) as u32);
-        let total_coinbase_outputs: u64 = coinbase_transaction
-            .outputs
-            .iter()
-            .map(|output| output.value)
-            .sum();
-        // if the coinbase value does not match the expected it is an invalid coinbase
-        // transaction
-        if total_coinbase_outputs != block_reward + miner_fees {
+        if !self.coinbase_spends_mature(height, transaction, &self.utxos) {
+            return Err(BtcError::InvalidTransactionInput);
+        }
+        if !self.sequence_locks_met_at(height, transaction, &self.utxos) {
             return Err(BtcError::InvalidTransaction);
         }
-        Ok(())
+        transaction.verify_money_range(self.params.max_money)?;
+        transaction.verify(&self.utxos)
     }
 
-    pub fn calculate_miner_fees(
-        &self,
-        utxos: &HashMap<Hash, TransactionOutput>,
-    ) -> Result<u64> {
-        let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
-        let mut outputs: HashMap<Hash, TransactionOutput> = HashMap::new();
-
-        //check every transaction after coinbase
-        for transaction in self.transactions.iter().skip(1) {
-            for input in &transaction.inputs {
-                //inputs do not contain the values of the outputs so we need to
-                //match inputs to outputs
-                let prev_output =
-                    utxos.get(&input.prev_transaction_output_hash);
-                if prev_output.is_none() {
-                    return Err(BtcError::InvalidTransaction);
-                }
-                let prev_output = prev_output.unwrap();
-                if inputs.contains_key(&input.prev_transaction_output_hash) {
-                    return Err(BtcError::InvalidTransaction);
+    /// Append headers without their transactions, as in headers-first
+    /// sync. Every header must extend the one before it; if any is invalid
+    /// none of them are imported. The bodies are added with `fill_body`,
+    /// and until they all are `add_block` rejects new blocks.
+    pub fn import_headers(&mut self, headers: Vec<BlockHeader>) -> Result<()> {
+        let initial_height = self.blocks.len();
+        for header in headers {
+            if !self.header_is_valid(self.block_height(), &header) {
+                for block in self.blocks.drain(initial_height..) {
+                    self.chain_work.remove(&block.hash());
+                    self.unconnected -= 1;
                 }
-                //we populate the hashmap with the outputs hash and the transaction
-                //outputs which produce the inputs of the current transactions.
-                inputs.insert(
-                    input.prev_transaction_output_hash,
-                    prev_output.clone(),
-                );
-            }
-            for output in &transaction.outputs {
-                //Avoid adding the same output twice
-                if outputs.contains_key(&output.hash()) {
-                    return Err(BtcError::InvalidTransaction);
-                }
-                outputs.insert(output.hash(), output.clone());
+                return Err(BtcError::InvalidBlockHeader);
             }
+            self.record_chain_work(&header);
+            self.blocks.push(Block::new(header, vec![]));
+            self.unconnected += 1;
         }
-        let input_value: u64 = inputs.values().map(|output| output.value).sum();
-        let output_value: u64 =
-            outputs.values().map(|output| output.value).sum();
-        //The fee is the difference between the input value and the output value of all
-        // transactions
-        Ok(input_value - output_value)
+        Ok(())
     }
-}
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct BlockHeader {
-    ///the time when the block was created.
-    pub timestamp: DateTime<Utc>,
-    /// number only used once, we increment it to mine the block
-    pub nonce: u64,
-    /// the hash of the previous block in the chain
-    pub prev_block_hash: Hash,
-    /// the hash of the Merkñe tree root derived from all of the transactions in this block.
-    /// This ensures that all transactions are accounted for and unalterable without changing
-    /// the header
-    pub merkle_root: MerkleRoot,
-    ///A number, which has to be higher than the hash of this block for it to be considered valid
-    pub target: U256,
-}
+    /// Attach the transactions of a block whose header was imported with
+    /// `import_headers`. They must match the header's merkle root.
+    ///
+    /// Transactions can only be validated against the UTXO set of all the
+    /// blocks before them, so bodies may arrive in any order: one filled
+    /// after a missing body is kept and validated once the gap is filled.
+    /// Once validated, a body is connected like `add_block` does: applied
+    /// to the UTXO set, with its undo data and txids recorded. A body
+    /// found invalid is removed again and its error returned.
+    pub fn fill_body(
+        &mut self,
+        height: u64,
+        transactions: Vec<Transaction>,
+    ) -> Result<()> {
+        let connected = self.block_height() - self.unconnected;
+        if height < connected {
+            return Err(BtcError::InvalidBlock);
+        }
+        let block = self
+            .blocks
+            .get(height as usize)
+            .ok_or(BtcError::InvalidBlock)?;
+        if !block.transactions.is_empty() {
+            return Err(BtcError::InvalidBlock);
+        }
+        let block = Block::new(block.header.clone(), transactions);
+        block.verify_merkle_root()?;
+        self.blocks[height as usize] = block;
+        self.connect_filled_bodies()
+    }
 
-impl BlockHeader {
-    pub fn new(
-        timestamp: DateTime<Utc>,
-        nonce: u64,
-        prev_block_hash: Hash,
-        merkle_root: MerkleRoot,
-        target: U256,
-    ) -> Self {
-        BlockHeader {
-            timestamp,
-            nonce,
-            prev_block_hash,
-            merkle_root,
+    // Validate and connect the filled bodies following the connected
+    // blocks, in order, until one is missing. An invalid body is removed
+    // again.
+    fn connect_filled_bodies(&mut self) -> Result<()> {
+        while self.unconnected > 0 {
+            let height = self.block_height() - self.unconnected;
+            let block = &self.blocks[height as usize];
+            // a valid block always has a coinbase, so an empty body is
+            // missing
+            if block.transactions.is_empty() {
+                break;
+            }
+            let result = if self.has_duplicate_transactions(block) {
+                Err(BtcError::InvalidTransaction)
+            } else {
+                self.verify_body_at(height, block, &self.utxos)
+            };
+            if let Err(e) = result {
+                self.blocks[height as usize].transactions = vec![];
+                return Err(e);
+            }
+            let block = &self.blocks[height as usize];
+            let undo = self.utxos.apply_block(block, height);
+            self.undo.insert(block.hash(), undo);
+            self.txids
+                .extend(block.transactions.iter().map(Transaction::hash));
+            self.unconnected -= 1;
+            self.notify_connected(height);
+        }
+        Ok(())
+    }
+
+    /// The UTXO set as it was after the first `height` blocks, rebuilt by
+    /// replaying them, so it costs O(height). With the chain's
+    /// `block_height` it is the current UTXO set.
+    pub fn utxos_at_height(&self, height: u64) -> UtxoSet {
+        let mut utxos = UtxoSet::new();
+        for (height, block) in
+            self.blocks.iter().take(height as usize).enumerate()
+        {
+            utxos.apply_block(block, height as u64);
+        }
+        utxos
+    }
+
+    /// Fee paid by the transaction `txid` already in the chain.
+    ///
+    /// Its inputs may have been spent since, so their values are looked up
+    /// in the blocks rather than in the UTXO set. A coinbase pays no fee.
+    pub fn historical_fee(&self, txid: &Hash) -> Result<Amount> {
+        let (_, transaction) = self
+            .find_transaction(txid)
+            .ok_or(BtcError::TransactionNotFound)?;
+        if transaction.inputs.is_empty() {
+            return Ok(Amount::ZERO);
+        }
+        let mut input_value = Amount::ZERO;
+        for input in &transaction.inputs {
+            let prev_output = self
+                .find_transaction(&input.prev_output.txid)
+                .and_then(|(_, transaction)| {
+                    transaction.outputs.get(input.prev_output.vout as usize)
+                })
+                .ok_or(BtcError::InvalidTransactionInput)?;
+            input_value = input_value
+                .checked_add(prev_output.value)
+                .ok_or(BtcError::InvalidTransaction)?;
+        }
+        let output_value = Amount::checked_sum(
+            transaction.outputs.iter().map(|output| output.value),
+        )
+        .ok_or(BtcError::InvalidTransaction)?;
+        input_value
+            .checked_sub(output_value)
+            .ok_or(BtcError::InvalidTransaction)
+    }
+
+    /// Seconds elapsed between each pair of consecutive blocks, so the
+    /// result has one entry less than the chain has blocks.
+    pub fn block_intervals(&self) -> Vec<i64> {
+        self.blocks
+            .windows(2)
+            .map(|pair| {
+                (pair[1].header.timestamp - pair[0].header.timestamp)
+                    .num_seconds()
+            })
+            .collect()
+    }
+
+    /// Time elapsed between the tip's timestamp and `now`, negative if the
+    /// tip is timestamped in the future. An empty chain is as old as the
+    /// unix epoch.
+    pub fn tip_age(&self, now: DateTime<Utc>) -> chrono::Duration {
+        let tip_time = self
+            .blocks
+            .last()
+            .map_or(DateTime::UNIX_EPOCH, |block| block.header.timestamp);
+        now - tip_time
+    }
+
+    /// Average number of transactions in the last `last_n` blocks (or the
+    /// whole chain if shorter), counting the coinbases only if
+    /// `include_coinbase` is set. Pruned blocks count as empty; an empty
+    /// range averages 0.
+    pub fn avg_transactions_per_block(
+        &self,
+        last_n: u64,
+        include_coinbase: bool,
+    ) -> f64 {
+        let start = self.blocks.len().saturating_sub(last_n as usize);
+        let blocks = &self.blocks[start..];
+        if blocks.is_empty() {
+            return 0.0;
+        }
+        let skip = if include_coinbase { 0 } else { 1 };
+        let transactions: usize = blocks
+            .iter()
+            .map(|block| block.transactions.len().saturating_sub(skip))
+            .sum();
+        transactions as f64 / blocks.len() as f64
+    }
+
+    /// Check that every block points at the hash of the block before it,
+    /// and the first one at the zero hash. Only headers are looked at, so
+    /// this also works on a pruned chain and catches a corrupted or
+    /// reordered `blocks` vector without revalidating any transactions.
+    pub fn verify_linkage(&self) -> Result<()> {
+        let mut prev_hash = Hash::zero();
+        for block in &self.blocks {
+            if block.header.prev_block_hash != prev_hash {
+                return Err(BtcError::InvalidBlock);
+            }
+            prev_hash = block.hash();
+        }
+        Ok(())
+    }
+
+    /// Target the next block must meet. It only changes every
+    /// `difficulty_update_interval` blocks of the chain parameters, scaled
+    /// by how long the last interval took compared to `ideal_block_time`
+    /// per block: slower blocks make it larger (easier), faster ones
+    /// smaller (harder). The change is limited to a factor of
+    /// `max_target_adjustment` and the result never gets easier than
+    /// `min_target`, which is also the target of the genesis block.
+    /// `add_block` rejects blocks with any other.
+    pub fn next_target(&self) -> U256 {
+        self.next_target_at(self.block_height())
+    }
+
+    // Target of the block following the first `height` blocks.
+    fn next_target_at(&self, height: u64) -> U256 {
+        headers::next_target(&self.params, &self.blocks, height)
+    }
+
+    /// State of `deployment` for the block at `height`, which can be at most
+    /// the next block's (`block_height`).
+    ///
+    /// The state changes at the start of each window of
+    /// `miner_confirmation_window` blocks, depending on the median time
+    /// past then and, once started, on how many blocks of the window before
+    /// signalled. It is recomputed from the genesis block every time.
+    pub fn deployment_state(
+        &self,
+        deployment: &Deployment,
+        height: u64,
+    ) -> DeploymentState {
+        let height = height.min(self.block_height());
+        let window = self.params.miner_confirmation_window;
+        let mut state = DeploymentState::Defined;
+        if window == 0 {
+            return state;
+        }
+        let mut window_start = window;
+        while window_start <= height {
+            let median_time_past = self.median_time_past_at(window_start);
+            state = match state {
+                DeploymentState::Defined
+                    if median_time_past >= deployment.timeout =>
+                {
+                    DeploymentState::Failed
+                }
+                DeploymentState::Defined
+                    if median_time_past >= deployment.start_time =>
+                {
+                    DeploymentState::Started
+                }
+                DeploymentState::Started => {
+                    let signalling = self.blocks[(window_start - window)
+                        as usize
+                        ..window_start as usize]
+                        .iter()
+                        .filter(|block| {
+                            deployment.signalled_by(block.header.version)
+                        })
+                        .count() as u64;
+                    if signalling
+                        >= self.params.rule_change_activation_threshold
+                    {
+                        DeploymentState::LockedIn
+                    } else if median_time_past >= deployment.timeout {
+                        DeploymentState::Failed
+                    } else {
+                        DeploymentState::Started
+                    }
+                }
+                DeploymentState::LockedIn => DeploymentState::Active,
+                state => state,
+            };
+            window_start += window;
+        }
+        state
+    }
+
+    /// Version for the next block: the version bits top bits, with the bit
+    /// of every deployment started or locked in set to signal for it.
+    pub fn next_block_version(&self) -> u32 {
+        let height = self.block_height();
+        self.params
+            .deployments
+            .iter()
+            .filter(|deployment| {
+                matches!(
+                    self.deployment_state(deployment, height),
+                    DeploymentState::Started | DeploymentState::LockedIn
+                )
+            })
+            .fold(crate::VERSIONBITS_TOP_BITS, |version, deployment| {
+                version | 1 << deployment.bit
+            })
+    }
+
+    /// Sum of the work of every block in the chain, which decides which
+    /// branch is active rather than its height.
+    pub fn total_work(&self) -> U256 {
+        self.blocks
+            .last()
+            .and_then(|tip| self.chain_work(&tip.hash()))
+            .unwrap_or_default()
+    }
+
+    /// Hashes of blocks of the active chain from the tip back to the
+    /// genesis block, for a peer to find where its chain forks from ours
+    /// with `find_fork_point`: the last 10 blocks, then ever larger gaps
+    /// doubling each time, always ending with the genesis block.
+    pub fn block_locator(&self) -> Vec<Hash> {
+        let mut locator = vec![];
+        let Some(mut height) = self.blocks.len().checked_sub(1) else {
+            return locator;
+        };
+        let mut step = 1;
+        loop {
+            locator.push(self.blocks[height].hash());
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Height of the most recent block of the active chain that is in
+    /// `locator`, as built by a peer's `block_locator`, which is where the
+    /// peer's chain forks from ours. None if they share no block, not even
+    /// the genesis block.
+    pub fn find_fork_point(&self, locator: &[Hash]) -> Option<u64> {
+        let heights: HashMap<Hash, usize> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(height, block)| (block.hash(), height))
+            .collect();
+        // the locator starts from the peer's tip, so the first match is
+        // the highest
+        locator
+            .iter()
+            .find_map(|hash| heights.get(hash))
+            .map(|height| *height as u64)
+    }
+
+    /// Every known tip: the active chain's and those of the competing
+    /// branches, like bitcoin's `getchaintips`. The active tip comes first,
+    /// then the others by decreasing height.
+    pub fn chain_tips(&self) -> Vec<ChainTip> {
+        let mut tips = vec![];
+        if let Some(tip) = self.blocks.last() {
+            tips.push(ChainTip {
+                hash: tip.hash(),
+                height: self.block_height() - 1,
+                work: self.total_work(),
+                status: ChainTipStatus::Active,
+            });
+        }
+        // the active blocks all build on each other, so the other tips are
+        // the side blocks no side block builds on
+        let parents: HashSet<Hash> = self
+            .side_blocks
+            .values()
+            .map(|block| block.header.prev_block_hash)
+            .collect();
+        let mut forks: Vec<ChainTip> = self
+            .side_blocks
+            .keys()
+            .filter(|hash| !parents.contains(hash))
+            .filter_map(|&hash| {
+                let (fork_height, branch) = self.branch_of(hash)?;
+                let status = if branch
+                    .iter()
+                    .any(|hash| self.invalid_blocks.contains(hash))
+                {
+                    ChainTipStatus::Invalid
+                } else {
+                    ChainTipStatus::ValidFork
+                };
+                Some(ChainTip {
+                    hash,
+                    height: (fork_height + branch.len() - 1) as u64,
+                    work: self.chain_work(&hash).unwrap_or_default(),
+                    status,
+                })
+            })
+            .collect();
+        forks.sort_by(|a, b| b.height.cmp(&a.height).then(a.hash.cmp(&b.hash)));
+        tips.extend(forks);
+        tips
+    }
+
+    /// Total work of the chain ending at the block `hash`, from the
+    /// genesis block, for blocks of the active chain and of competing
+    /// branches. None for unknown blocks.
+    pub fn chain_work(&self, hash: &Hash) -> Option<U256> {
+        self.chain_work.get(hash).copied()
+    }
+
+    /// Sync progress as the ratio of the chain's `total_work` to the work
+    /// the complete chain is assumed to have, clamped to 1.0.
+    pub fn sync_progress(&self, assumed_total_work: U256) -> f64 {
+        let total_work = self.total_work();
+        if total_work >= assumed_total_work {
+            return 1.0;
+        }
+        util::u256_to_f64(total_work) / util::u256_to_f64(assumed_total_work)
+    }
+
+    /// Median timestamp of the last `MEDIAN_TIME_SPAN` blocks, the time
+    /// time-locked transactions are compared against and new block
+    /// timestamps must exceed. Unlike a single block timestamp it can't be
+    /// moved much by one miner.
+    pub fn median_time_past(&self) -> DateTime<Utc> {
+        self.median_time_past_at(self.block_height())
+    }
+
+    // Median time past for the block at `height`, computed from the blocks
+    // before it. It is the unix epoch if there are none.
+    fn median_time_past_at(&self, height: u64) -> DateTime<Utc> {
+        headers::median_time_past(&self.blocks, height)
+    }
+
+    /// List the unspent outputs paying `pubkey` whose value is below the
+    /// dust threshold, identified by their outpoint.
+    // Unsolicited dust is a common way of tracking wallets: if the victim
+    // spends it together with their other coins the outputs get linked, so
+    // wallets can use this to warn before doing so.
+    pub fn dust_outputs_for(&self, pubkey: &PublicKey) -> Vec<Outpoint> {
+        self.iter_utxos_for_pubkey(pubkey)
+            .filter(|(_, output)| output.value < crate::DUST_THRESHOLD)
+            .map(|(outpoint, _)| *outpoint)
+            .collect()
+    }
+
+    /// The unspent outputs paying `pubkey` with their value, largest
+    /// first, so users can pick the inputs of a transaction by hand.
+    pub fn utxos_for_sorted(
+        &self,
+        pubkey: &PublicKey,
+    ) -> Vec<(Outpoint, Amount)> {
+        let mut utxos: Vec<(Outpoint, Amount)> = self
+            .iter_utxos_for_pubkey(pubkey)
+            .map(|(outpoint, output)| (*outpoint, output.value))
+            .collect();
+        // break ties by key so the order doesn't depend on the HashMap
+        utxos.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        utxos
+    }
+
+    /// Balance of `pubkey` as (confirmed, pending). The confirmed balance
+    /// only counts the UTXO set, the pending one is what it would be once
+    /// every transaction of `mempool` is mined: without the outputs they
+    /// spend and with the ones they create.
+    ///
+    /// The mempool may have been filled against another chain, or before a
+    /// reorg, so nothing is assumed about its transactions: the sums
+    /// saturate at the largest amount rather than overflow.
+    pub fn balance_with_mempool(
+        &self,
+        pubkey: &PublicKey,
+        mempool: &Mempool,
+    ) -> (Amount, Amount) {
+        let saturated = Amount::from_sats(u64::MAX);
+        let add = |a: Amount, b: Amount| a.checked_add(b).unwrap_or(saturated);
+        let confirmed = self
+            .iter_utxos_for_pubkey(pubkey)
+            .fold(Amount::ZERO, |total, (_, output)| add(total, output.value));
+        let mut spent = Amount::ZERO;
+        let mut received = Amount::ZERO;
+        for transaction in mempool.transactions() {
+            for input in &transaction.inputs {
+                if let Some(output) = self.utxos.get(&input.prev_output) {
+                    if output.pubkey == *pubkey {
+                        spent = add(spent, output.value);
+                    }
+                }
+            }
+            received = transaction
+                .outputs
+                .iter()
+                .filter(|output| output.pubkey == *pubkey)
+                .fold(received, |total, output| add(total, output.value));
+        }
+        let pending = add(
+            confirmed.checked_sub(spent).unwrap_or(Amount::ZERO),
+            received,
+        );
+        (confirmed, pending)
+    }
+
+    /// Rough estimate in bytes of the memory taken by the chain state: the
+    /// UTXO set and the indexes kept next to it (the txids, the undo data
+    /// and the chain work of every block), summing the size of every key
+    /// and value without the maps' own overhead. Coins hold no heap data,
+    /// so their size is fixed; undo data also counts the coins it keeps.
+    pub fn chainstate_memory(&self) -> usize {
+        use std::mem::size_of;
+        let utxos =
+            self.utxos.len() * (size_of::<Outpoint>() + size_of::<Coin>());
+        let txids = self.txids.len() * size_of::<Hash>();
+        let undo: usize = self
+            .undo
+            .values()
+            .map(|undo| {
+                size_of::<Hash>()
+                    + size_of::<BlockUndo>()
+                    + undo.spent.len() * size_of::<(Outpoint, Coin)>()
+            })
+            .sum();
+        let chain_work =
+            self.chain_work.len() * (size_of::<Hash>() + size_of::<U256>());
+        utxos + txids + undo + chain_work
+    }
+
+    /// Whether the output stored under `outpoint` is still unspent at the
+    /// tip of the chain.
+    pub fn is_unspent(&self, outpoint: &Outpoint) -> bool {
+        self.utxos.contains(outpoint)
+    }
+
+    /// How many more blocks must be added before the coinbase output
+    /// `outpoint` can be spent, or None if it isn't an unspent coinbase
+    /// output or it can already be spent in the next block.
+    pub fn coinbase_maturity_remaining(
+        &self,
+        outpoint: &Outpoint,
+    ) -> Option<u64> {
+        let height = self.block_height();
+        let coin = self.utxos.coin(outpoint).filter(|coin| coin.is_coinbase)?;
+        self.params
+            .coinbase_spendable_height(coin.height)
+            .checked_sub(height)
+            .filter(|remaining| *remaining > 0)
+    }
+
+    /// Check whether `outpoint` is in the UTXO set without leaking it
+    /// through timing.
+    ///
+    /// A `HashMap` lookup returns faster or slower depending on whether the
+    /// key exists, which a privacy-sensitive server may not want to reveal.
+    /// This instead compares against every entry of the set without
+    /// short-circuiting, so it costs O(n) per query: only use it where that
+    /// tradeoff is acceptable.
+    pub fn contains_utxo_ct(&self, outpoint: &Outpoint) -> bool {
+        let needle = outpoint.to_bytes();
+        let mut found = 0u8;
+        for (key, _) in self.utxos.iter() {
+            // accumulate the differences of every byte, the result is zero
+            // only when all of them match
+            let diff = key
+                .to_bytes()
+                .iter()
+                .zip(needle.iter())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+            found |= (diff == 0) as u8;
+        }
+        found == 1
+    }
+
+    /// Ratio of the value still held in the UTXO set to the total supply
+    /// issued by block rewards so far. Anything missing was destroyed, e.g.
+    /// by a miner claiming less than the allowed coinbase value. An empty
+    /// chain has issued nothing, so its ratio is 0.
+    pub fn liquid_supply_ratio(&self) -> f64 {
+        let issued: u64 = (0..self.block_height())
+            .map(|height| self.params.block_reward(height).to_sats())
+            .sum();
+        if issued == 0 {
+            return 0.0;
+        }
+        let unspent: u64 = self
+            .utxos
+            .values()
+            .map(|output| output.value.to_sats())
+            .sum();
+        unspent as f64 / issued as f64
+    }
+
+    /// Describe the next block to mine as a getblocktemplate-like JSON
+    /// document, for pool software.
+    ///
+    /// The candidates are the transactions of `mempool`, highest fee rate
+    /// first. Those failing `verify_transaction` against the chain as it is
+    /// now, bigger than `MAX_STANDARD_TX_SIZE`, with more than
+    /// `MAX_STANDARD_TX_SIGOPS` signatures or that would make the block
+    /// exceed the chain's size, transaction count or sigop limits, are left
+    /// out. The coinbase paying `miner` the reward plus all fees is
+    /// included ready to use. Transactions are hex encoded in their
+    /// ciborium serialization.
+    pub fn get_block_template_json(
+        &self,
+        mempool: &Mempool,
+        miner: &PublicKey,
+    ) -> String {
+        let height = self.block_height();
+        let previous_block_hash = self
+            .blocks
+            .last()
+            .map_or(Hash::zero(), |block| block.hash());
+        let target = self.next_target();
+        // size of the block with just the coinbase, overestimated by using
+        // the largest nonce and coinbase value and leaving room for the
+        // transaction count to take more bytes
+        let largest_coinbase = Transaction::coinbase(
+            height,
+            vec![TransactionOutput {
+                value: Amount::from_sats(u64::MAX),
+                unique_id: Uuid::nil(),
+                pubkey: miner.clone(),
+            }],
+        );
+        let now = self.clock.now();
+        let mut largest_header = BlockHeader::new(
+            now,
+            u64::MAX,
+            previous_block_hash,
+            MerkleRoot::calculate(std::slice::from_ref(&largest_coinbase)),
             target,
+        );
+        largest_header.version = u32::MAX;
+        let mut block_size = Block::new(largest_header, vec![largest_coinbase])
+            .serialized_size()
+            + 8;
+        // the coinbase has no input, so no signature
+        let mut sigops = 0;
+        let mut included = vec![];
+        let mut spent: HashSet<Outpoint> = HashSet::new();
+        let mut fees = Amount::ZERO;
+        for transaction in &mempool.select_transactions(mempool.len()) {
+            // the coinbase takes one of the block's transactions
+            if included.len() + 1 >= self.params.max_transactions_per_block {
+                break;
+            }
+            // huge transactions are valid but we don't relay nor mine them,
+            // and the block must stay within its size limit
+            let size = transaction.serialized_size();
+            if size > crate::MAX_STANDARD_TX_SIZE
+                || block_size + size > self.params.max_block_size
+            {
+                continue;
+            }
+            // likewise for the signatures to verify
+            let transaction_sigops = transaction.sigop_count();
+            if transaction_sigops > crate::MAX_STANDARD_TX_SIGOPS
+                || sigops + transaction_sigops > self.params.max_block_sigops
+            {
+                continue;
+            }
+            // skip the ones conflicting with an already included transaction,
+            // and those the chain moved past since they were added, like
+            // spending outputs a block since spent
+            if transaction
+                .inputs
+                .iter()
+                .any(|input| spent.contains(&input.prev_output))
+                || self.verify_transaction(transaction).is_err()
+            {
+                continue;
+            }
+            // verified above, so the fee is known
+            let Ok(fee) = transaction.fee(&self.utxos) else {
+                continue;
+            };
+            for input in &transaction.inputs {
+                spent.insert(input.prev_output);
+            }
+            block_size += size;
+            sigops += transaction_sigops;
+            // fees are bounded by the UTXO values, they can't overflow
+            fees = fees.checked_add(fee).unwrap();
+            included.push(serde_json::json!({
+                "data": hex_encode(transaction),
+                "txid": transaction.hash().to_string(),
+                "fee": fee.to_sats(),
+            }));
         }
+        let coinbase_value =
+            self.params.block_reward(height).checked_add(fees).unwrap();
+        let coinbase = Transaction::coinbase(
+            height,
+            vec![TransactionOutput {
+                value: coinbase_value,
+                unique_id: Uuid::new_v4(),
+                pubkey: miner.clone(),
+            }],
+        );
+        let min_time = self.median_time_past().timestamp() + 1;
+        serde_json::json!({
+            "version": self.next_block_version(),
+            "previousblockhash": previous_block_hash.to_string(),
+            "height": height,
+            "target": format!("{:064x}", target),
+            "curtime": now.timestamp(),
+            "mintime": min_time,
+            "coinbasevalue": coinbase_value.to_sats(),
+            "coinbasetxn": { "data": hex_encode(&coinbase) },
+            "transactions": included,
+        })
+        .to_string()
     }
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+
+    /// Compare the value of the UTXO set with what the chain issued: the
+    /// block rewards up to the tip, minus what coinbases left unclaimed of
+    /// their reward and fees. A mismatch means a validation bug let coins
+    /// be created or destroyed.
+    ///
+    /// The fees are computed from the undo data, so this fails with
+    /// `MissingUndoData` on a pruned chain.
+    pub fn audit_supply(&self) -> Result<SupplyAudit> {
+        // a consensus change being tried out may let blocks create more than
+        // MAX_MONEY, overflowing the sums: they saturate so the audit still
+        // reports the inflation
+        let saturated = Amount::from_sats(u64::MAX);
+        let mut issued = Amount::ZERO;
+        let mut unclaimed = Amount::ZERO;
+        for (height, block) in self.blocks.iter().enumerate() {
+            let undo = self
+                .undo
+                .get(&block.hash())
+                .ok_or(BtcError::MissingUndoData)?;
+            let reward = self.params.block_reward(height as u64);
+            let spent = Amount::checked_sum(
+                undo.spent.iter().map(|(_, coin)| coin.output.value),
+            )
+            .unwrap_or(saturated);
+            let created = Amount::checked_sum(
+                block
+                    .transactions
+                    .iter()
+                    .skip(1)
+                    .flat_map(|transaction| &transaction.outputs)
+                    .map(|output| output.value),
+            )
+            .unwrap_or(saturated);
+            // transactions creating more than they spend inflate the UTXO
+            // set, which the comparison catches
+            let fees = spent.checked_sub(created).unwrap_or(Amount::ZERO);
+            let claimed = Amount::checked_sum(
+                block
+                    .transactions
+                    .first()
+                    .into_iter()
+                    .flat_map(|coinbase| &coinbase.outputs)
+                    .map(|output| output.value),
+            )
+            .unwrap_or(saturated);
+            issued = issued.checked_add(reward).unwrap_or(saturated);
+            // claiming too much shows up as a larger UTXO set instead
+            let left = reward
+                .checked_add(fees)
+                .unwrap_or(saturated)
+                .checked_sub(claimed)
+                .unwrap_or(Amount::ZERO);
+            unclaimed = unclaimed.checked_add(left).unwrap_or(saturated);
+        }
+        let utxo_total =
+            Amount::checked_sum(self.utxos.values().map(|output| output.value))
+                .unwrap_or(saturated);
+        Ok(SupplyAudit {
+            issued,
+            unclaimed,
+            utxo_total,
+        })
+    }
+
+    /// Shannon entropy, in bits, of the distribution of UTXO values.
+    ///
+    /// Values are bucketed by their order of magnitude in base 2 (zero has
+    /// its own bucket), so the result ranges from 0 when every UTXO is in
+    /// the same bucket to log2 of the number of buckets used.
+    pub fn utxo_value_entropy(&self) -> f64 {
+        let mut buckets: HashMap<u32, u64> = HashMap::new();
+        for output in self.utxos.values() {
+            // bucket n holds the values with n significant bits
+            let bucket = u64::BITS - output.value.to_sats().leading_zeros();
+            *buckets.entry(bucket).or_default() += 1;
+        }
+        let total = self.utxos.len() as f64;
+        buckets
+            .values()
+            .map(|count| {
+                let p = *count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// The `top_n` most frequent output values across every output ever
+    /// created in the chain, with how many outputs had each value, most
+    /// frequent first.
+    pub fn common_denominations(&self, top_n: usize) -> Vec<(Amount, u64)> {
+        let mut counts: HashMap<Amount, u64> = HashMap::new();
+        for output in self
+            .blocks
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .flat_map(|transaction| &transaction.outputs)
+        {
+            *counts.entry(output.value).or_default() += 1;
+        }
+        let mut counts: Vec<(Amount, u64)> = counts.into_iter().collect();
+        // break ties by value so the order doesn't depend on the HashMap
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(top_n);
+        counts
+    }
+
+    /// How many outputs each pubkey has received across the whole chain,
+    /// spent or not. Any count above 1 is a reused address, which links
+    /// those payments together.
+    pub fn address_reuse_count(&self) -> HashMap<PublicKey, u64> {
+        let mut counts: HashMap<PublicKey, u64> = HashMap::new();
+        for output in self
+            .blocks
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .flat_map(|transaction| &transaction.outputs)
+        {
+            *counts.entry(output.pubkey.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Check a merkle proof, e.g. sent by a light client, that `transaction`
+    /// is in the block `block_hash`. Only the stored header is needed, so
+    /// pruned blocks can be checked too. Fails with `InvalidHash` if the
+    /// block is unknown and `InvalidMerkleRoot` if the proof doesn't match.
+    pub fn verify_external_proof(
+        &self,
+        block_hash: &Hash,
+        transaction: &Transaction,
+        proof: &MerkleProof,
+    ) -> Result<()> {
+        let block = self
+            .blocks
+            .iter()
+            .find(|block| block.hash() == *block_hash)
+            .ok_or(BtcError::InvalidHash)?;
+        if !proof.verify(transaction, &block.header.merkle_root) {
+            return Err(BtcError::InvalidMerkleRoot);
+        }
+        Ok(())
+    }
+
+    /// Package the block at `height` with a merkle proof for each of its
+    /// transactions, so a light client can verify any of them offline.
+    pub fn block_bundle(&self, height: u64) -> Option<BlockBundle> {
+        let block = self.blocks.get(height as usize)?;
+        let proofs = (0..block.transactions.len())
+            .map(|index| MerkleRoot::proof(&block.transactions, index))
+            .collect::<Option<Vec<_>>>()?;
+        Some(BlockBundle {
+            header: block.header.clone(),
+            transactions: block.transactions.clone(),
+            proofs,
+        })
+    }
+
+    /// The header of the block at `height` with a partial merkle tree of
+    /// the transactions in `txids` it contains, for light clients that
+    /// only want those. None if there is no block at `height`.
+    pub fn merkle_block(
+        &self,
+        height: u64,
+        txids: &[Hash],
+    ) -> Option<MerkleBlock> {
+        let block = self.blocks.get(height as usize)?;
+        Some(MerkleBlock {
+            header: block.header.clone(),
+            tree: PartialMerkleTree::new(&block.transactions, txids),
+        })
     }
+
+    /// Merkle proof that the transaction `txid` is in its block, with the
+    /// block's hash, for light clients like `spv::SpvClient`. None if it
+    /// isn't in the chain or only in a pruned block.
+    pub fn transaction_proof(
+        &self,
+        txid: &Hash,
+    ) -> Option<(Hash, MerkleProof)> {
+        self.blocks.iter().find_map(|block| {
+            let index = block
+                .transactions
+                .iter()
+                .position(|transaction| transaction.hash() == *txid)?;
+            let proof = MerkleRoot::proof(&block.transactions, index)?;
+            Some((block.hash(), proof))
+        })
+    }
+
+    // The UTXO set is committed to as a merkle tree whose leaves are the
+    // hashes of every (key, output) pair, sorted by key so the tree is the
+    // same no matter the HashMap iteration order.
+    fn utxo_leaves(&self) -> Vec<(Outpoint, Hash)> {
+        let mut utxos: Vec<_> = self.utxos.iter().collect();
+        utxos.sort_by_key(|(key, _)| **key);
+        utxos
+            .into_iter()
+            .map(|(key, output)| (*key, Self::utxo_leaf(key, output)))
+            .collect()
+    }
+
+    fn utxo_leaf(outpoint: &Outpoint, output: &TransactionOutput) -> Hash {
+        util::merkle_leaf(&(outpoint, output))
+    }
+
+    /// Merkle root committing to the whole UTXO set, zero if it is empty.
+    pub fn utxo_commitment(&self) -> Hash {
+        let leaves: Vec<Hash> = self
+            .utxo_leaves()
+            .into_iter()
+            .map(|(_, leaf)| leaf)
+            .collect();
+        util::merkle_root(&leaves)
+    }
+
+    /// Merkle path proving that the UTXO stored under `outpoint` is part of
+    /// `utxo_commitment`. Returns `None` if the output is not unspent, so a
+    /// server can't produce a proof for it.
+    pub fn utxo_merkle_proof(
+        &self,
+        outpoint: &Outpoint,
+    ) -> Option<Vec<(Hash, bool)>> {
+        let leaves = self.utxo_leaves();
+        let index = leaves.iter().position(|(key, _)| key == outpoint)?;
+        let leaves: Vec<Hash> =
+            leaves.into_iter().map(|(_, leaf)| leaf).collect();
+        util::merkle_proof(&leaves, index)
+    }
+
+    /// Verifies a proof returned by `utxo_merkle_proof` against a UTXO
+    /// commitment. It doesn't need the chain, so light wallets can check
+    /// a server's answer knowing only the commitment.
+    pub fn verify_utxo_proof(
+        outpoint: &Outpoint,
+        output: &TransactionOutput,
+        proof: &[(Hash, bool)],
+        commitment: &Hash,
+    ) -> bool {
+        util::verify_merkle_proof(
+            Self::utxo_leaf(outpoint, output),
+            proof,
+            *commitment,
+        )
+    }
+
+    /// Proof that nothing is stored under `outpoint` in the UTXO set
+    /// committed to by `utxo_commitment`: the UTXOs right before and after
+    /// it in the sorted leaves, with their merkle paths. Returns `None` if
+    /// the output is unspent.
+    pub fn utxo_absence_proof(
+        &self,
+        outpoint: &Outpoint,
+    ) -> Option<UtxoAbsenceProof> {
+        let leaves = self.utxo_leaves();
+        // where the outpoint would be inserted, if not already there
+        let index = leaves
+            .binary_search_by(|(key, _)| key.cmp(outpoint))
+            .err()?;
+        let hashes: Vec<Hash> = leaves.iter().map(|(_, leaf)| *leaf).collect();
+        let neighbour = |index: usize| {
+            let (outpoint, _) = leaves.get(index)?;
+            Some(UtxoProofLeaf {
+                outpoint: *outpoint,
+                output: self.utxos.get(outpoint)?.clone(),
+                proof: util::merkle_proof(&hashes, index)?,
+            })
+        };
+        Some(UtxoAbsenceProof {
+            before: index.checked_sub(1).and_then(neighbour),
+            after: neighbour(index),
+        })
+    }
+
+    /// Verifies a proof returned by `utxo_absence_proof` against a UTXO
+    /// commitment: both neighbours must be in the tree, next to each other
+    /// and on either side of `outpoint`. A missing neighbour must be made
+    /// up for by the other being the first or last leaf, or by the tree
+    /// being empty if both are.
+    pub fn verify_utxo_absence(
+        outpoint: &Outpoint,
+        proof: &UtxoAbsenceProof,
+        commitment: &Hash,
+    ) -> bool {
+        let verify = |leaf: &UtxoProofLeaf| {
+            let hash = Self::utxo_leaf(&leaf.outpoint, &leaf.output);
+            util::verify_merkle_proof(hash, &leaf.proof, *commitment)
+                .then(|| util::merkle_proof_index(&leaf.proof))
+                .flatten()
+        };
+        match (&proof.before, &proof.after) {
+            (None, None) => *commitment == Hash::zero(),
+            // the first leaf is the leftmost at every layer
+            (None, Some(after)) => {
+                after.outpoint > *outpoint && verify(after) == Some(0)
+            }
+            (Some(before), None) => {
+                before.outpoint < *outpoint
+                    && verify(before).is_some()
+                    && util::merkle_proof_is_last(
+                        Self::utxo_leaf(&before.outpoint, &before.output),
+                        &before.proof,
+                    )
+            }
+            (Some(before), Some(after)) => {
+                before.outpoint < *outpoint
+                    && after.outpoint > *outpoint
+                    && before.proof.len() == after.proof.len()
+                    && match (verify(before), verify(after)) {
+                        (Some(before), Some(after)) => {
+                            before.checked_add(1) == Some(after)
+                        }
+                        _ => false,
+                    }
+            }
+        }
+    }
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Hex encoding of the ciborium serialization of a value.
+fn hex_encode<T: Serialize>(value: &T) -> String {
+    hex::encode(util::serialize(value))
 }
+
+/// Time spent in each phase of `Blockchain::validate_timed`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidationTimings {
+    /// linkage, proof of work and timestamp checks
+    pub headers: Duration,
+    /// recomputing the merkle roots
+    pub merkle: Duration,
+    /// verifying the transactions, dominated by their signatures
+    pub signatures: Duration,
+}
+
+/// Proof that an outpoint isn't in a UTXO set, as returned by
+/// `Blockchain::utxo_absence_proof`. The leaves are sorted by outpoint, so
+/// two adjacent leaves on either side of it show there's no room for it.
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Transaction {
-    pub inputs: Vec<TransactionInput>,
-    pub outputs: Vec<TransactionOutput>,
+pub struct UtxoAbsenceProof {
+    /// The greatest UTXO before the outpoint, none if it would be first.
+    pub before: Option<UtxoProofLeaf>,
+    /// The smallest UTXO after the outpoint, none if it would be last.
+    pub after: Option<UtxoProofLeaf>,
 }
 
-impl Transaction {
-    pub fn new(
-        inputs: Vec<TransactionInput>,
-        outputs: Vec<TransactionOutput>,
-    ) -> Self {
-        Transaction { inputs, outputs }
+/// A UTXO with the merkle path proving it is part of a UTXO commitment.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UtxoProofLeaf {
+    pub outpoint: Outpoint,
+    pub output: TransactionOutput,
+    pub proof: Vec<(Hash, bool)>,
+}
+
+/// Supply of a chain, as returned by `Blockchain::audit_supply`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupplyAudit {
+    /// Sum of the block rewards of every block.
+    pub issued: Amount,
+    /// Rewards and fees the coinbases didn't claim, lost forever.
+    pub unclaimed: Amount,
+    /// Value of the UTXO set.
+    pub utxo_total: Amount,
+}
+
+impl SupplyAudit {
+    /// Whether the UTXO set holds exactly what was issued and claimed.
+    pub fn is_consistent(&self) -> bool {
+        self.issued.checked_sub(self.unclaimed) == Some(self.utxo_total)
     }
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+}
+
+/// The last block of a branch, as returned by `Blockchain::chain_tips`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainTip {
+    pub hash: Hash,
+    /// Height of the block, the genesis block's being 0.
+    pub height: u64,
+    /// Total work of the branch from the genesis block.
+    pub work: U256,
+    pub status: ChainTipStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainTipStatus {
+    /// The tip of the active chain.
+    Active,
+    /// A competing branch with less work, not known to be invalid. Only
+    /// the branches that were active once have been fully validated.
+    ValidFork,
+    /// A branch with a block that failed validation, which will never be
+    /// active.
+    Invalid,
+}
+
+/// A block together with the merkle proof of each of its transactions,
+/// meant to be serialized as a single blob for light clients.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockBundle {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+    /// `proofs[i]` is the merkle path of `transactions[i]`
+    pub proofs: Vec<MerkleProof>,
+}
+
+impl BlockBundle {
+    /// Check that every transaction is committed to by the header's merkle
+    /// root.
+    pub fn verify(&self) -> bool {
+        self.transactions.len() == self.proofs.len()
+            && self.transactions.iter().zip(&self.proofs).all(
+                |(transaction, proof)| {
+                    proof.verify(transaction, &self.header.merkle_root)
+                },
+            )
     }
 }
 
+/// A block header with a partial merkle tree of some of its transactions,
+/// like Bitcoin's merkleblock message. The matched transactions themselves
+/// are sent separately.
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct TransactionInput {
-    /// Hash of the transaction output that we are going to use as input/// Real bitcoin uses a slightly different scheme - it stores the
-    /// previous transaction hash, and the index of the output in
-    /// that transaction.
-    pub prev_transaction_output_hash: Hash,
-    /// Signature of the user which proves they can expend the output
-    /// of the previous transaction. In the real implementation of bitcoin it is required a
-    /// script field instead just the pubkey. The main implementation of bitcoin can do many things in the script fields, but we are fine with a much simpler solution, where you can only send sats to a recipient and nothing else.
-    pub signature: Signature,
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub tree: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    /// Position and leaf hash of the matched transactions, None if the
+    /// tree is malformed or doesn't match the header's merkle root.
+    pub fn matches(&self) -> Option<Vec<(usize, Hash)>> {
+        let (root, matches) = self.tree.extract_matches()?;
+        (root == self.header.merkle_root).then_some(matches)
+    }
+}
+
+// Lets the header checks work on blocks and headers alike.
+impl AsRef<BlockHeader> for Block {
+    fn as_ref(&self) -> &BlockHeader {
+        &self.header
+    }
 }
+
+/// A block is a collection of transactions with a header.
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct TransactionOutput {
-    /// Value of the transaction output
-    pub value: u64,
-    /// Identifier of the transaction output
-    pub unique_id: Uuid,
-    /// Pubkey of the recipient, the signature of the corresponding
-    /// private key must be used for signing the following transaction
-    pub pubkey: PublicKey,
+pub struct Block {
+    /// The header of the block
+    pub header: BlockHeader,
+    /// The transactions in the block
+    pub transactions: Vec<Transaction>,
 }
-impl TransactionOutput {
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+impl Block {
+    pub fn new(header: BlockHeader, transactions: Vec<Transaction>) -> Self {
+        Block {
+            // in the new function if the parameter has the same name
+            // as the field you can write just once instead of field
+            // Block{header:header, transaction: transaction}
+            header,
+            transactions,
+        }
+        //this allows the function to be unimpemented but will crash at
+        //runtime
+    }
+    /// The first block of a chain with the given parameters, paying the
+    /// block reward to `coinbase_pubkey`. Everything else is fixed (the
+    /// timestamp comes from the parameters, the output id is nil and the
+    /// nonce is the lowest that works), so the same inputs always give the
+    /// same block. Fails with `InvalidBlock` if no nonce meets
+    /// `min_target`.
+    pub fn genesis(
+        params: &ChainParams,
+        coinbase_pubkey: PublicKey,
+    ) -> Result<Self> {
+        let coinbase = Transaction::coinbase(
+            0,
+            vec![TransactionOutput {
+                value: params.block_reward(0),
+                unique_id: Uuid::nil(),
+                pubkey: coinbase_pubkey,
+            }],
+        );
+        let transactions = vec![coinbase];
+        let mut header = BlockHeader::new(
+            params.genesis_timestamp,
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            params.min_target,
+        );
+        // any target leaves a chance for no nonce to work, but for sane
+        // ones it is negligible
+        if !header.mine(u64::MAX) {
+            return Err(BtcError::InvalidBlock);
+        }
+        Ok(Block::new(header, transactions))
+    }
+    // The merkle root in the header already commits to the transactions,
+    // so the block is identified by its header alone. This also keeps the
+    // hash of a pruned block (see `Blockchain::prune`) unchanged.
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+    /// Checks that don't need the chain, so they can be done as soon as the
+    /// block arrives, even if its parent is unknown: it has transactions,
+    /// the first and only the first of them is a coinbase, its merkle root
+    /// matches them and its hash meets its own target. Whether that target
+    /// is the right one is for `Blockchain::contextual_check`.
+    pub fn check(&self) -> Result<()> {
+        let Some((coinbase, transactions)) = self.transactions.split_first()
+        else {
+            return Err(BtcError::InvalidBlock);
+        };
+        if !coinbase.inputs.is_empty()
+            || transactions
+                .iter()
+                .any(|transaction| transaction.inputs.is_empty())
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+        self.verify_merkle_root()?;
+        if !self.header.hash().matches_target(self.header.target) {
+            return Err(BtcError::InvalidBlock);
+        }
+        Ok(())
+    }
+    /// Check that the header's merkle root matches the transactions.
+    pub fn verify_merkle_root(&self) -> Result<()> {
+        if MerkleRoot::calculate(&self.transactions) != self.header.merkle_root
+        {
+            return Err(BtcError::InvalidMerkleRoot);
+        }
+        Ok(())
+    }
+    /// Recompute the header's merkle root from the current transactions,
+    /// needed after changing them (e.g. the coinbase extra nonce). This
+    /// changes the block hash, so the block must be mined again.
+    pub fn refresh_merkle_root(&mut self) {
+        self.header.merkle_root = MerkleRoot::calculate(&self.transactions);
+    }
+    /// Mine the block, trying every nonce from the current one up to
+    /// `u64::MAX`. Each time they run out, the extra nonce in the coinbase
+    /// data is bumped, which changes the merkle root and gives a fresh
+    /// range of nonces starting at 0. Returns false if no valid hash was
+    /// found after `max_rounds` extra nonce bumps, or if the block has no
+    /// coinbase to bump.
+    ///
+    /// The extra nonce is the 8 bytes following the height the coinbase
+    /// data starts with, appended if the data is shorter, so the height
+    /// commitment is never overwritten.
+    pub fn mine_with_extranonce(&mut self, max_rounds: u64) -> bool {
+        // where the extra nonce goes, after the height or whatever shorter
+        // data the coinbase has
+        let offset = self
+            .transactions
+            .first()
+            .map_or(0, |coinbase| coinbase.coinbase_data.len().min(8));
+        for round in 0..=max_rounds {
+            if round > 0 {
+                let Some(coinbase) = self.transactions.first_mut() else {
+                    return false;
+                };
+                let data = &mut coinbase.coinbase_data;
+                let range = offset..offset + 8;
+                if data.len() < range.end {
+                    data.resize(range.end, 0);
+                }
+                let extra_nonce =
+                    u64::from_le_bytes(data[range.clone()].try_into().unwrap());
+                data[range].copy_from_slice(
+                    &extra_nonce.wrapping_add(1).to_le_bytes(),
+                );
+                self.refresh_merkle_root();
+                self.header.nonce = 0;
+            }
+            if self.header.mine(u64::MAX) {
+                return true;
+            }
+        }
+        false
+    }
+    /// How lucky the miner was: the difficulty the block hash actually
+    /// reached divided by the difficulty required by its target, both
+    /// relative to `max_target`. Above 1.0 the hash is further below the
+    /// target than needed.
+    ///
+    /// This is a single sample, so it is very noisy. The hash is uniformly
+    /// distributed below the target, so the median luck is 2.0 and the
+    /// mean is unbounded. A zero hash or target is counted as 1 to keep the
+    /// result finite.
+    pub fn luck(&self, max_target: U256) -> f64 {
+        let max_target = util::u256_to_f64(max_target);
+        let hash = U256::from_little_endian(&self.header.hash().as_bytes())
+            .max(U256::one());
+        let target = self.header.target.max(U256::one());
+        let achieved_difficulty = max_target / util::u256_to_f64(hash);
+        let required_difficulty = max_target / util::u256_to_f64(target);
+        achieved_difficulty / required_difficulty
+    }
+    /// Edges of the dependency graph between the block's transactions, as
+    /// (spender txid, spent txid) pairs: one for every transaction spending
+    /// an output created by another transaction of the same block.
+    pub fn dependency_edges(&self) -> Vec<(Hash, Hash)> {
+        let txids: HashSet<Hash> = self
+            .transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect();
+        let mut edges = vec![];
+        for transaction in &self.transactions {
+            let txid = transaction.hash();
+            for input in &transaction.inputs {
+                let spent_txid = input.prev_output.txid;
+                // spending several outputs of the same parent is still a
+                // single dependency
+                if txids.contains(&spent_txid)
+                    && !edges.contains(&(txid, spent_txid))
+                {
+                    edges.push((txid, spent_txid));
+                }
+            }
+        }
+        edges
+    }
+    /// Txids of every transaction of the block spending an output of
+    /// `txid`, directly or through other transactions of the block, in
+    /// block order.
+    pub fn descendants_of(&self, txid: &Hash) -> Vec<Hash> {
+        let edges = self.dependency_edges();
+        let mut descendants: HashSet<Hash> = HashSet::new();
+        let mut pending = vec![*txid];
+        while let Some(parent) = pending.pop() {
+            for (child, _) in edges.iter().filter(|(_, spent)| *spent == parent)
+            {
+                if descendants.insert(*child) {
+                    pending.push(*child);
+                }
+            }
+        }
+        self.transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .filter(|txid| descendants.contains(txid))
+            .collect()
+    }
+    /// Serialize the block with ciborium, the same encoding used for hashing.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        util::serialize(self)
+    }
+    /// Deserialize a block received from an untrusted source, following the
+    /// limits of `params`.
+    ///
+    /// This never panics. Every count is checked as soon as it is read,
+    /// before anything is allocated for it: more transactions than
+    /// `max_transactions_per_block` or outputs than `max_block_size` fail
+    /// with `BtcError::BlockTooLarge`, and more inputs than
+    /// `max_block_sigops` (every input is a signature to verify) with
+    /// `BtcError::TooManySigops`. Input larger than `max_block_size` is
+    /// `BlockTooLarge` too, and anything else malformed is
+    /// `InvalidBlockEncoding`.
+    pub fn from_bytes(bytes: &[u8], params: &ChainParams) -> Result<Block> {
+        if bytes.len() > params.max_block_size {
+            return Err(BtcError::BlockTooLarge);
+        }
+        decode::decode_block(bytes, params)
+    }
+    /// Size in bytes of the block serialized by `to_bytes`.
+    pub fn serialized_size(&self) -> usize {
+        self.to_bytes().len()
+    }
+    /// Check that the block has at most `max_transactions_per_block`
+    /// transactions and at most `max_block_size` bytes once serialized.
+    pub fn verify_size(&self, params: &ChainParams) -> Result<()> {
+        // counting is much cheaper than serializing, so it goes first
+        if self.transactions.len() > params.max_transactions_per_block
+            || self.serialized_size() > params.max_block_size
+        {
+            return Err(BtcError::BlockTooLarge);
+        }
+        Ok(())
+    }
+    /// Check everything `check` and `verify_transactions` do, and report
+    /// every rule broken instead of stopping at the first one, for
+    /// debugging and teaching.
+    ///
+    /// The fees of invalid transactions aren't counted in the value the
+    /// coinbase may claim.
+    pub fn validate(
+        &self,
+        predicted_block_height: u64,
+        utxos: &UtxoSet,
+        params: &ChainParams,
+    ) -> ValidationReport {
+        let mut report = ValidationReport::new();
+        if self.transactions.is_empty() {
+            report.add("empty-block", None, None);
+            return report;
+        }
+        if self.verify_merkle_root().is_err() {
+            report.add("merkle-root", None, None);
+        }
+        if !self.header.hash().matches_target(self.header.target) {
+            report.add("proof-of-work", None, None);
+        }
+        if self.verify_size(params).is_err() {
+            report.add("block-size", None, None);
+        }
+        if self.verify_sigops(params).is_err() {
+            report.add("block-sigops", None, None);
+        }
+        let mut spent: HashSet<Outpoint> = HashSet::new();
+        let mut fees = Amount::ZERO;
+        for (index, transaction) in self.transactions.iter().enumerate() {
+            let violations_before = report.violations.len();
+            if transaction.verify_money_range(params.max_money).is_err() {
+                report.add("money-range", Some(index), None);
+            }
+            // the coinbase is checked once the fees are known
+            if index == 0 {
+                continue;
+            }
+            if transaction.inputs.is_empty() {
+                report.add("coinbase-position", Some(index), None);
+            }
+            let mut input_value = Some(Amount::ZERO);
+            for (input_index, input) in transaction.inputs.iter().enumerate() {
+                if !spent.insert(input.prev_output) {
+                    report.add(
+                        "duplicate-input",
+                        Some(index),
+                        Some(input_index),
+                    );
+                }
+                let Some(prev_output) = utxos.get(&input.prev_output) else {
+                    report.add("missing-input", Some(index), Some(input_index));
+                    input_value = None;
+                    continue;
+                };
+                input_value = input_value
+                    .and_then(|value| value.checked_add(prev_output.value));
+                let signed = transaction
+                    .sighash(input_index, prev_output.value, input.sighash_type)
+                    .is_some_and(|sighash| {
+                        input.signature.verify(&sighash, &prev_output.pubkey)
+                    });
+                if !signed {
+                    report.add("bad-signature", Some(index), Some(input_index));
+                }
+            }
+            // the fee can't be known without the value of every input
+            let Some(input_value) = input_value else {
+                continue;
+            };
+            let fee = Amount::checked_sum(
+                transaction.outputs.iter().map(|output| output.value),
+            )
+            .and_then(|output_value| input_value.checked_sub(output_value));
+            match fee {
+                Some(fee) if report.violations.len() == violations_before => {
+                    // the inputs are UTXOs, their values can't overflow
+                    fees = fees.checked_add(fee).unwrap();
+                }
+                Some(_) => {}
+                None => report.add("outputs-exceed-inputs", Some(index), None),
+            }
+        }
+        let coinbase = &self.transactions[0];
+        if !coinbase.inputs.is_empty() {
+            report.add("coinbase-inputs", Some(0), None);
+        }
+        if coinbase.outputs.is_empty() {
+            report.add("coinbase-outputs", Some(0), None);
+        }
+        if coinbase.coinbase_height() != Some(predicted_block_height) {
+            report.add("coinbase-height", Some(0), None);
+        }
+        let claimed = Amount::checked_sum(
+            coinbase.outputs.iter().map(|output| output.value),
+        );
+        let expected = params
+            .block_reward(predicted_block_height)
+            .checked_add(fees);
+        if claimed.is_none() || claimed != expected {
+            report.add("coinbase-value", Some(0), None);
+        }
+        report
+    }
+    /// Number of signatures verifying all the block's transactions takes.
+    pub fn sigop_count(&self) -> u64 {
+        self.transactions.iter().map(Transaction::sigop_count).sum()
+    }
+    /// Check the block doesn't take more than `max_block_sigops` signature
+    /// verifications.
+    pub fn verify_sigops(&self, params: &ChainParams) -> Result<()> {
+        if self.sigop_count() > params.max_block_sigops {
+            return Err(BtcError::TooManySigops);
+        }
+        Ok(())
+    }
+    //Verify all transactions in the block
+    //A transactions must:
+    // - have the input from a UTXO
+    // - do not spend the same input twice in the block
+    // -  has a valid signature
+    // - has a output value less or equal than the input value
+    pub fn verify_transactions(
+        &self,
+        predicted_block_height: u64,
+        utxos: &UtxoSet,
+        params: &ChainParams,
+    ) -> Result<()> {
+        self.verify_all_transactions(
+            predicted_block_height,
+            utxos,
+            params,
+            true,
+        )
+    }
+    /// Like `verify_transactions`, but trusting the signatures, for the
+    /// `assume_valid` block of the chain parameters and its ancestors.
+    pub fn verify_transactions_without_signatures(
+        &self,
+        predicted_block_height: u64,
+        utxos: &UtxoSet,
+        params: &ChainParams,
+    ) -> Result<()> {
+        self.verify_all_transactions(
+            predicted_block_height,
+            utxos,
+            params,
+            false,
+        )
+    }
+    // Check the transactions as `verify_transactions` describes, their
+    // signatures only if `check_signatures`.
+    fn verify_all_transactions(
+        &self,
+        predicted_block_height: u64,
+        utxos: &UtxoSet,
+        params: &ChainParams,
+        check_signatures: bool,
+    ) -> Result<()> {
+        let mut inputs: HashSet<Outpoint> = HashSet::new();
+        //reject completely empty blocks
+        if self.transactions.is_empty() {
+            return Err(BtcError::InvalidBlock);
+        }
+        self.verify_size(params)?;
+        self.verify_sigops(params)?;
+        //verify coinbase transaction
+        self.verify_coinbase_transaction(
+            predicted_block_height,
+            utxos,
+            params,
+        )?;
+
+        //the coinbase has no inputs and was checked above
+        for transaction in self.transactions.iter().skip(1) {
+            //Prevents same-block double-spending, if a input is already in the
+            //inputs set it means that a previous transaction in the same
+            //block comes from the same input
+            for input in &transaction.inputs {
+                if !inputs.insert(input.prev_output) {
+                    return Err(BtcError::InvalidTransaction);
+                }
+            }
+            transaction.verify_money_range(params.max_money)?;
+            if check_signatures {
+                transaction.verify(utxos)?;
+            } else {
+                transaction.verify_without_signatures(utxos)?;
+            }
+        }
+        Ok(())
+    }
+    pub fn verify_coinbase_transaction(
+        &self,
+        predicted_block_height: u64,
+        utxos: &UtxoSet,
+        params: &ChainParams,
+    ) -> Result<()> {
+        //Coinbase transaction is the first transation in the block
+        let coinbase_transaction = &self.transactions[0];
+        //The coinbase transaction generates new BTC
+        // it must not have any input
+        if !coinbase_transaction.inputs.is_empty() {
+            return Err(BtcError::InvalidTransaction);
+        }
+        //It must always generate new BTC, outputs can not be 0
+        if coinbase_transaction.outputs.is_empty() {
+            return Err(BtcError::InvalidTransaction);
+        }
+        coinbase_transaction.verify_money_range(params.max_money)?;
+        //It must commit to the height of its block
+        if coinbase_transaction.coinbase_height()
+            != Some(predicted_block_height)
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+        //get the value of the whole block fee
+        let miner_fees = self.calculate_miner_fees(utxos)?;
+        //get the value of the expected new bitcoin minned
+        let block_reward = params.block_reward(predicted_block_height);
+        // `.sum()` would silently wrap in release builds, so a coinbase
+        // whose outputs overflow u64 must be rejected explicitly
+        let total_coinbase_outputs = Amount::checked_sum(
+            coinbase_transaction
+                .outputs
+                .iter()
+                .map(|output| output.value),
+        )
+        .ok_or(BtcError::InvalidTransaction)?;
+        let expected_coinbase_value = block_reward
+            .checked_add(miner_fees)
+            .ok_or(BtcError::InvalidTransaction)?;
+        // if the coinbase value does not match the expected it is an invalid coinbase
+        // transaction
+        if total_coinbase_outputs != expected_coinbase_value {
+            return Err(BtcError::InvalidTransaction);
+        }
+        Ok(())
+    }
+
+    pub fn calculate_miner_fees(&self, utxos: &UtxoSet) -> Result<Amount> {
+        let mut inputs: HashMap<Outpoint, TransactionOutput> = HashMap::new();
+        let mut outputs: HashMap<Outpoint, TransactionOutput> = HashMap::new();
+
+        //check every transaction after coinbase
+        for transaction in self.transactions.iter().skip(1) {
+            for input in &transaction.inputs {
+                //inputs do not contain the values of the outputs so we need to
+                //match inputs to outputs
+                let prev_output = utxos.get(&input.prev_output);
+                if prev_output.is_none() {
+                    return Err(BtcError::InvalidTransaction);
+                }
+                let prev_output = prev_output.unwrap();
+                if inputs.contains_key(&input.prev_output) {
+                    return Err(BtcError::InvalidTransaction);
+                }
+                //we populate the hashmap with the outputs hash and the transaction
+                //outputs which produce the inputs of the current transactions.
+                inputs.insert(input.prev_output, prev_output.clone());
+            }
+            let txid = transaction.hash();
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                //Avoid adding the same output twice
+                let outpoint = Outpoint::new(txid, vout as u32);
+                if outputs.contains_key(&outpoint) {
+                    return Err(BtcError::InvalidTransaction);
+                }
+                outputs.insert(outpoint, output.clone());
+            }
+        }
+        // the values come from untrusted transactions, so overflowing sums
+        // and spending more than the inputs are errors rather than panics
+        let sum = |outputs: &HashMap<Outpoint, TransactionOutput>| {
+            Amount::checked_sum(outputs.values().map(|output| output.value))
+                .ok_or(BtcError::InvalidTransaction)
+        };
+        let input_value = sum(&inputs)?;
+        let output_value = sum(&outputs)?;
+        //The fee is the difference between the input value and the output value of all
+        // transactions
+        input_value
+            .checked_sub(output_value)
+            .ok_or(BtcError::InvalidTransaction)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockHeader {
+    /// Version bits signalling for soft fork deployments, see
+    /// `Blockchain::deployment_state`.
+    pub version: u32,
+    ///the time when the block was created.
+    pub timestamp: DateTime<Utc>,
+    /// number only used once, we increment it to mine the block
+    pub nonce: u64,
+    /// the hash of the previous block in the chain
+    pub prev_block_hash: Hash,
+    /// the hash of the Merkñe tree root derived from all of the transactions in this block.
+    /// This ensures that all transactions are accounted for and unalterable without changing
+    /// the header
+    pub merkle_root: MerkleRoot,
+    ///A number, which has to be higher than the hash of this block for it to be considered valid
+    pub target: U256,
+}
+
+impl AsRef<BlockHeader> for BlockHeader {
+    fn as_ref(&self) -> &BlockHeader {
+        self
+    }
+}
+
+impl BlockHeader {
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        nonce: u64,
+        prev_block_hash: Hash,
+        merkle_root: MerkleRoot,
+        target: U256,
+    ) -> Self {
+        BlockHeader {
+            version: crate::VERSIONBITS_TOP_BITS,
+            timestamp,
+            nonce,
+            prev_block_hash,
+            merkle_root,
+            target,
+        }
+    }
+    pub fn hash(&self) -> Hash {
+        Hash::hash(self)
+    }
+    /// Size in bytes of the ciborium serialization of the header. Integers
+    /// are encoded with as few bytes as possible, so unlike bitcoin's fixed
+    /// 80 bytes this depends slightly on the values.
+    pub fn serialized_size(&self) -> usize {
+        util::serialize(self).len()
+    }
+    /// Look for a nonce that makes the hash match the target, trying every
+    /// value from the current nonce up to `max_nonce`. On success the
+    /// winning nonce is left in place and true is returned.
+    ///
+    /// If none of them works, false is returned with the nonce at
+    /// `max_nonce`. Mining this header again would then retry the same
+    /// hashes, so the caller must change something else first, like the
+    /// timestamp or the coinbase extra nonce.
+    pub fn mine(&mut self, max_nonce: u64) -> bool {
+        loop {
+            if self.hash().matches_target(self.target) {
+                return true;
+            }
+            if self.nonce >= max_nonce {
+                return false;
+            }
+            self.nonce += 1;
+        }
+    }
+    /// Expected number of hashes needed to mine a header with this target,
+    /// that is 2^256 / (target + 1).
+    pub fn work(&self) -> U256 {
+        // 2^256 doesn't fit in a U256, but 2^256 / (target + 1) is the same as
+        // (2^256 - target - 1) / (target + 1) + 1, and 2^256 - target - 1 is
+        // just !target. A zero target would need 2^256, so it saturates.
+        match self.target.checked_add(U256::one()) {
+            Some(divisor) => {
+                (!self.target / divisor).saturating_add(U256::one())
+            }
+            None => U256::one(),
+        }
+    }
+    /// Order two headers by how hard they were to mine, a harder header is
+    /// greater. A smaller target is harder, so the targets are compared in
+    /// reverse, which avoids converting them to a floating point difficulty.
+    pub fn cmp_difficulty(&self, other: &BlockHeader) -> Ordering {
+        other.target.cmp(&self.target)
+    }
+}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Transaction {
+    pub inputs: Vec<TransactionInput>,
+    pub outputs: Vec<TransactionOutput>,
+    /// The transaction can't be included in a block until this height
+    /// (values below `LOCKTIME_THRESHOLD`) or unix time (values above it)
+    /// has passed. Zero means it isn't locked.
+    pub lock_time: u64,
+    /// Free data only used by coinbase transactions, which must start it
+    /// with the height of their block, see `Transaction::coinbase`. Miners
+    /// use the next 8 bytes as an extra nonce.
+    pub coinbase_data: Vec<u8>,
+}
+
+impl Transaction {
+    pub fn new(
+        inputs: Vec<TransactionInput>,
+        outputs: Vec<TransactionOutput>,
+    ) -> Self {
+        Transaction {
+            inputs,
+            outputs,
+            lock_time: 0,
+            coinbase_data: vec![],
+        }
+    }
+    /// Create the coinbase transaction of the block at `height`.
+    ///
+    /// Like bitcoin's BIP34, the coinbase commits to the height of its block
+    /// (as the first 8 bytes of `coinbase_data`, little endian), so two
+    /// coinbases paying the same outputs still have different hashes.
+    pub fn coinbase(height: u64, outputs: Vec<TransactionOutput>) -> Self {
+        let mut transaction = Transaction::new(vec![], outputs);
+        transaction.coinbase_data = height.to_le_bytes().to_vec();
+        transaction
+    }
+    /// Height committed to by a coinbase transaction, if any.
+    pub fn coinbase_height(&self) -> Option<u64> {
+        let bytes = self.coinbase_data.get(..8)?;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+    pub fn hash(&self) -> Hash {
+        Hash::hash(self)
+    }
+    //Verify the transaction against a UTXO set
+    //A transactions must:
+    // - have the input from a UTXO
+    // - do not spend the same input twice
+    // -  has a valid signature
+    // - has a output value less or equal than the input value
+    pub fn verify(&self, utxos: &UtxoSet) -> Result<()> {
+        self.verify_inputs(utxos, true)
+    }
+    /// Like `verify`, but trusting the signatures, for the `assume_valid`
+    /// block of the chain parameters and its ancestors.
+    pub fn verify_without_signatures(&self, utxos: &UtxoSet) -> Result<()> {
+        self.verify_inputs(utxos, false)
+    }
+    // Check the inputs spend distinct outputs of `utxos`, and their
+    // signatures if `check_signatures`, and that the outputs don't spend
+    // more than them.
+    fn verify_inputs(
+        &self,
+        utxos: &UtxoSet,
+        check_signatures: bool,
+    ) -> Result<()> {
+        let mut inputs: HashSet<Outpoint> = HashSet::new();
+        for (index, input) in self.inputs.iter().enumerate() {
+            //If the transaction inputs does not come from an
+            //UTXO it is not valid
+            let prev_output = utxos
+                .get(&input.prev_output)
+                .ok_or(BtcError::InvalidTransaction)?;
+            if !inputs.insert(input.prev_output) {
+                return Err(BtcError::InvalidTransaction);
+            }
+            if !check_signatures {
+                continue;
+            }
+
+            // check if the signature is valid
+            let sighash = self
+                .sighash(index, prev_output.value, input.sighash_type)
+                .ok_or(BtcError::InvalidSignature)?;
+            if !input.signature.verify(&sighash, &prev_output.pubkey) {
+                return Err(BtcError::InvalidSignature);
+            }
+        }
+        // the output value can't be more than the input value
+        self.fee(utxos)?;
+        Ok(())
+    }
+    /// Check that no output is worth more than `max_money`, nor all of them
+    /// together, so no transaction can create more coins than can exist.
+    pub fn verify_money_range(&self, max_money: Amount) -> Result<()> {
+        let mut total = Amount::ZERO;
+        for output in &self.outputs {
+            total = total
+                .checked_add(output.value)
+                .filter(|total| *total <= max_money)
+                .ok_or(BtcError::InvalidTransaction)?;
+        }
+        Ok(())
+    }
+    /// Fee paid by the transaction: the value of the outputs it spends,
+    /// looked up in `utxos`, minus the value of its outputs.
+    pub fn fee(&self, utxos: &UtxoSet) -> Result<Amount> {
+        let mut input_value = Amount::ZERO;
+        for input in &self.inputs {
+            let prev_output = utxos
+                .get(&input.prev_output)
+                .ok_or(BtcError::InvalidTransaction)?;
+            input_value = input_value
+                .checked_add(prev_output.value)
+                .ok_or(BtcError::InvalidTransaction)?;
+        }
+        let output_value =
+            Amount::checked_sum(self.outputs.iter().map(|output| output.value))
+                .ok_or(BtcError::InvalidTransaction)?;
+        // It is fine for output value to be less than input value
+        // as the difference is the fee for the miner
+        // But we must be sure that the output is the same or less
+        // than the input value
+        input_value
+            .checked_sub(output_value)
+            .ok_or(BtcError::InvalidTransaction)
+    }
+    /// Whether the transaction is valid against at least one of `sets`.
+    ///
+    /// While two chain tips compete, a transaction may only be valid on one
+    /// of them; checking it against each tip's UTXO set tells whether it is
+    /// worth keeping around until the tie is resolved.
+    pub fn verify_against_any(&self, sets: &[&UtxoSet]) -> bool {
+        sets.iter().any(|utxos| self.verify(utxos).is_ok())
+    }
+    /// Bytes hashed to get the sighash of the input at `input_index`, which
+    /// spends an output worth `prev_value`. The layout is fixed so any
+    /// implementation can reproduce it, integers are little-endian:
+    ///
+    /// - format version, u32 (2)
+    /// - number of inputs, u32, then for each input the outpoint it spends
+    ///   (36 bytes, see `Outpoint::to_bytes`) and its sequence, u32
+    /// - `input_index`, u32
+    /// - `prev_value` in satoshis, u64
+    /// - sighash type, u8 (`All` 1, `None` 2, `Single` 3)
+    /// - number of outputs, u32, then for each committed output its value
+    ///   in satoshis, u64, its unique id (16 bytes) and its compressed
+    ///   pubkey (33 bytes)
+    /// - lock time, u64
+    ///
+    /// Signatures are never part of it. `All` commits to every output,
+    /// `None` to none and `Single` to the one at `input_index` only.
+    ///
+    /// None if there is no such input, or for `SighashType::Single` if
+    /// there is no output at the same index (bitcoin signs the constant 1
+    /// in that case, which lets anyone reuse the signature).
+    pub fn sighash_preimage(
+        &self,
+        input_index: usize,
+        prev_value: Amount,
+        sighash_type: SighashType,
+    ) -> Option<Vec<u8>> {
+        if input_index >= self.inputs.len() {
+            return None;
+        }
+        let outputs: Vec<&TransactionOutput> = match sighash_type {
+            SighashType::All => self.outputs.iter().collect(),
+            SighashType::None => vec![],
+            SighashType::Single => vec![self.outputs.get(input_index)?],
+        };
+        let mut preimage = vec![];
+        preimage.extend(SIGHASH_VERSION.to_le_bytes());
+        preimage.extend((self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            preimage.extend(input.prev_output.to_bytes());
+            preimage.extend(input.sequence.to_le_bytes());
+        }
+        preimage.extend((input_index as u32).to_le_bytes());
+        preimage.extend(prev_value.to_sats().to_le_bytes());
+        preimage.push(sighash_type.to_byte());
+        preimage.extend((outputs.len() as u32).to_le_bytes());
+        for output in outputs {
+            preimage.extend(output.value.to_sats().to_le_bytes());
+            preimage.extend(output.unique_id.as_bytes());
+            preimage.extend(output.pubkey.to_compressed_bytes());
+        }
+        preimage.extend(self.lock_time.to_le_bytes());
+        Some(preimage)
+    }
+    /// Hash signed by the input at `input_index`: the SHA-256 of its
+    /// `sighash_preimage`.
+    pub fn sighash(
+        &self,
+        input_index: usize,
+        prev_value: Amount,
+        sighash_type: SighashType,
+    ) -> Option<Hash> {
+        self.sighash_preimage(input_index, prev_value, sighash_type)
+            .map(|preimage| Hash::digest(&preimage))
+    }
+    /// Sign the input at `input_index`, spending an output worth
+    /// `prev_value`, with its own sighash type. Inputs can be signed in any
+    /// order, as signatures aren't part of any sighash.
+    pub fn sign_input(
+        &mut self,
+        input_index: usize,
+        prev_value: Amount,
+        private_key: &PrivateKey,
+    ) -> Result<()> {
+        let sighash_type = self
+            .inputs
+            .get(input_index)
+            .ok_or(BtcError::InvalidTransactionInput)?
+            .sighash_type;
+        let sighash = self
+            .sighash(input_index, prev_value, sighash_type)
+            .ok_or(BtcError::InvalidTransactionInput)?;
+        self.inputs[input_index].signature =
+            Signature::sign_output(&sighash, private_key);
+        Ok(())
+    }
+    /// Whether the transaction follows `policy`, see `StandardnessPolicy`.
+    /// Only the transaction itself is checked, not its validity.
+    pub fn is_standard(&self, policy: &StandardnessPolicy) -> bool {
+        self.outputs.len() <= policy.max_outputs
+            && self.sigop_count() <= policy.max_sigops
+            && self.serialized_size() <= policy.max_size
+            && self
+                .outputs
+                .iter()
+                .all(|output| output.value >= policy.dust_threshold)
+    }
+    /// Number of signatures verifying the transaction takes, one per input.
+    /// Blocks and the mempool policy limit it, so a transaction can't be
+    /// cheap to create but expensive to verify.
+    pub fn sigop_count(&self) -> u64 {
+        self.inputs.len() as u64
+    }
+    /// Size in bytes of the ciborium serialization of the transaction.
+    pub fn serialized_size(&self) -> usize {
+        util::serialize(self).len()
+    }
+    /// Whether the transaction can be included in a block at `height`.
+    ///
+    /// Just like bitcoin, a time based lock is compared against the median
+    /// time past of the previous blocks rather than the block timestamp,
+    /// which a miner could otherwise set forward to unlock transactions
+    /// early. The lock is ignored if every input opted out of it by using
+    /// `SEQUENCE_FINAL`.
+    pub fn is_final(
+        &self,
+        height: u64,
+        median_time_past: DateTime<Utc>,
+    ) -> bool {
+        if self.lock_time == 0 {
+            return true;
+        }
+        let unlocked = if self.lock_time < crate::LOCKTIME_THRESHOLD {
+            self.lock_time < height
+        } else {
+            (self.lock_time as i64) < median_time_past.timestamp()
+        };
+        unlocked
+            || self
+                .inputs
+                .iter()
+                .all(|input| input.sequence == crate::SEQUENCE_FINAL)
+    }
+    /// Whether the relative lock times of the inputs (BIP68) have passed in
+    /// a block at `height`, `median_time_past` being the median time past
+    /// of the blocks before it.
+    ///
+    /// Unless `SEQUENCE_LOCKTIME_DISABLE_FLAG` is set, the low 16 bits of
+    /// an input's sequence are how many blocks, or 512 seconds units if
+    /// `SEQUENCE_LOCKTIME_TYPE_FLAG` is set, must have passed since the
+    /// output it spends was created. `origins[i]` is the height of the block
+    /// that created the output spent by input `i` with the median time past
+    /// before that block, or None if unknown, which counts as unlocked.
+    pub fn sequence_locks_met(
+        &self,
+        height: u64,
+        median_time_past: DateTime<Utc>,
+        origins: &[Option<(u64, DateTime<Utc>)>],
+    ) -> bool {
+        self.inputs.iter().zip(origins).all(|(input, origin)| {
+            if input.sequence & crate::SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                return true;
+            }
+            let Some((origin_height, origin_time)) = origin else {
+                return true;
+            };
+            let lock = (input.sequence & crate::SEQUENCE_LOCKTIME_MASK) as i64;
+            if input.sequence & crate::SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                (median_time_past - *origin_time).num_seconds()
+                    >= lock << crate::SEQUENCE_LOCKTIME_GRANULARITY
+            } else {
+                height.saturating_sub(*origin_height) >= lock as u64
+            }
+        })
+    }
+}
+
+/// Reference to a transaction output: the hash of the transaction that
+/// created it and the output's index in it. Like in bitcoin, the UTXO set
+/// is keyed by outpoints and inputs refer to the output they spend by one.
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+)]
+pub struct Outpoint {
+    pub txid: Hash,
+    pub vout: u32,
+}
+impl Outpoint {
+    pub fn new(txid: Hash, vout: u32) -> Self {
+        Outpoint { txid, vout }
+    }
+    /// Fixed size encoding: the 32 bytes of the txid (see `Hash::as_bytes`)
+    /// followed by the index as a little endian u32.
+    pub fn to_bytes(&self) -> [u8; 36] {
+        let mut bytes = [0; 36];
+        bytes[..32].copy_from_slice(&self.txid.as_bytes());
+        bytes[32..].copy_from_slice(&self.vout.to_le_bytes());
+        bytes
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransactionInput {
+    /// The output that we are going to use as input.
+    pub prev_output: Outpoint,
+    /// Signature of the user which proves they can expend the output
+    /// of the previous transaction. In the real implementation of bitcoin it is required a
+    /// script field instead just the pubkey. The main implementation of bitcoin can do many things in the script fields, but we are fine with a much simpler solution, where you can only send sats to a recipient and nothing else.
+    pub signature: Signature,
+    /// `SEQUENCE_FINAL` unless the input wants the transaction's lock time
+    /// to be enforced.
+    pub sequence: u32,
+    /// Which outputs the signature commits to.
+    pub sighash_type: SighashType,
+}
+impl TransactionInput {
+    pub fn new(prev_output: Outpoint, signature: Signature) -> Self {
+        TransactionInput {
+            prev_output,
+            signature,
+            sequence: crate::SEQUENCE_FINAL,
+            sighash_type: SighashType::All,
+        }
+    }
+}
+/// Which outputs of the transaction an input's signature commits to. The
+/// inputs are always committed to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SighashType {
+    /// All the outputs, so none of them can be changed.
+    All,
+    /// No output, anyone can decide where the coins go.
+    None,
+    /// Only the output with the same index as the input.
+    Single,
+}
+impl SighashType {
+    /// Byte identifying the type in the sighash preimage, the same values
+    /// bitcoin uses.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            SighashType::All => 1,
+            SighashType::None => 2,
+            SighashType::Single => 3,
+        }
+    }
+}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransactionOutput {
+    /// Value of the transaction output
+    pub value: Amount,
+    /// Identifier of the transaction output
+    pub unique_id: Uuid,
+    /// Pubkey of the recipient, the signature of the corresponding
+    /// private key must be used for signing the following transaction
+    pub pubkey: PublicKey,
+}
+impl TransactionOutput {
+    pub fn hash(&self) -> Hash {
+        Hash::hash(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::test_util::{self, output, spend, Event};
+    use std::sync::Mutex;
+
+    #[test]
+    fn dust_outputs_are_listed_for_their_owner_only() {
+        let key = PrivateKey::new_key();
+        let other = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction = spend(
+            &key,
+            &[funds],
+            vec![
+                output(545, &key.public_key()),
+                output(546, &key.public_key()),
+                output(10, &other),
+            ],
+        );
+        let txid = transaction.hash();
+        let block = test_util::next_block(&chain, vec![transaction], &other);
+        chain.add_block(block).unwrap();
+
+        assert_eq!(
+            chain.dust_outputs_for(&key.public_key()),
+            vec![Outpoint::new(txid, 0)]
+        );
+        assert_eq!(
+            chain.dust_outputs_for(&other),
+            vec![Outpoint::new(txid, 2)]
+        );
+    }
+
+    #[test]
+    fn coinbase_outputs_overflowing_u64_are_rejected() {
+        let miner = PrivateKey::new_key().public_key();
+        // the outputs would wrap around to exactly the reward
+        let params = ChainParams {
+            initial_reward: Amount::from_sats(1),
+            max_money: Amount::from_sats(u64::MAX),
+            ..test_util::params()
+        };
+        let coinbase = Transaction::coinbase(
+            0,
+            vec![output(u64::MAX, &miner), output(2, &miner)],
+        );
+        let transactions = vec![coinbase];
+        let header = BlockHeader::new(
+            params.genesis_timestamp,
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            params.min_target,
+        );
+        let block = Block::new(header, transactions);
+        assert!(block
+            .verify_coinbase_transaction(0, &UtxoSet::new(), &params)
+            .is_err());
+    }
+
+    // A chain whose UTXO set holds the `count` outputs of one transaction
+    // paying `key`.
+    fn chain_with_utxos(key: &PrivateKey, count: u64) -> Blockchain {
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let outputs = (0..count)
+            .map(|_| output(funds.1.to_sats() / count, &key.public_key()))
+            .collect();
+        let transaction = spend(key, &[funds], outputs);
+        let miner = PrivateKey::new_key().public_key();
+        let block = test_util::next_block(&chain, vec![transaction], &miner);
+        chain.add_block(block).unwrap();
+        chain
+    }
+
+    #[test]
+    fn utxo_inclusion_proofs_verify_against_the_commitment() {
+        let key = PrivateKey::new_key();
+        let chain = chain_with_utxos(&key, 5);
+        let commitment = chain.utxo_commitment();
+        for (outpoint, output) in chain.utxos.iter() {
+            let proof = chain.utxo_merkle_proof(outpoint).unwrap();
+            assert!(Blockchain::verify_utxo_proof(
+                outpoint,
+                output,
+                &proof,
+                &commitment
+            ));
+            let mut tampered = output.clone();
+            tampered.value = Amount::from_sats(1);
+            assert!(!Blockchain::verify_utxo_proof(
+                outpoint,
+                &tampered,
+                &proof,
+                &commitment
+            ));
+        }
+        let spent = test_util::coinbase_output(&chain.blocks[0]).0;
+        assert!(chain.utxo_merkle_proof(&spent).is_none());
+    }
+
+    #[test]
+    fn utxo_absence_proofs_verify_only_for_missing_outpoints() {
+        let key = PrivateKey::new_key();
+        for count in 1..=6 {
+            let chain = chain_with_utxos(&key, count);
+            let commitment = chain.utxo_commitment();
+            let mut present: Vec<Outpoint> =
+                chain.utxos.iter().map(|(outpoint, _)| *outpoint).collect();
+            present.sort();
+            for outpoint in &present {
+                assert!(chain.utxo_absence_proof(outpoint).is_none());
+            }
+            for seed in 0..20u64 {
+                let missing = Outpoint::new(Hash::hash(&seed), 0);
+                let proof = chain.utxo_absence_proof(&missing).unwrap();
+                assert!(Blockchain::verify_utxo_absence(
+                    &missing,
+                    &proof,
+                    &commitment
+                ));
+                // nor can the proof be reused for an unspent outpoint
+                for outpoint in &present {
+                    assert!(!Blockchain::verify_utxo_absence(
+                        outpoint,
+                        &proof,
+                        &commitment
+                    ));
+                }
+            }
+            // neighbours that aren't adjacent leave room for an outpoint
+            if present.len() >= 3 {
+                let leaf = |outpoint: &Outpoint| UtxoProofLeaf {
+                    outpoint: *outpoint,
+                    output: chain.utxos.get(outpoint).unwrap().clone(),
+                    proof: chain.utxo_merkle_proof(outpoint).unwrap(),
+                };
+                let skipping = UtxoAbsenceProof {
+                    before: Some(leaf(&present[0])),
+                    after: Some(leaf(&present[2])),
+                };
+                assert!(!Blockchain::verify_utxo_absence(
+                    &present[1],
+                    &skipping,
+                    &commitment
+                ));
+                let not_last = UtxoAbsenceProof {
+                    before: Some(leaf(&present[present.len() - 2])),
+                    after: None,
+                };
+                assert!(!Blockchain::verify_utxo_absence(
+                    &present[present.len() - 1],
+                    &not_last,
+                    &commitment
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_utxo_set_proves_every_absence() {
+        let chain = Blockchain::with_params(test_util::params());
+        let outpoint = Outpoint::new(Hash::zero(), 0);
+        let proof = chain.utxo_absence_proof(&outpoint).unwrap();
+        assert!(Blockchain::verify_utxo_absence(
+            &outpoint,
+            &proof,
+            &chain.utxo_commitment()
+        ));
+    }
+
+    #[test]
+    fn block_intervals_are_between_consecutive_blocks() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        assert!(chain.block_intervals().is_empty());
+        test_util::mine_blocks(&mut chain, 3, &miner);
+        assert_eq!(chain.block_intervals(), vec![10, 10, 10]);
+    }
+
+    #[test]
+    fn contains_utxo_ct_agrees_with_the_utxo_set() {
+        let key = PrivateKey::new_key();
+        let chain = chain_with_utxos(&key, 3);
+        for (outpoint, _) in chain.utxos.iter() {
+            assert!(chain.contains_utxo_ct(outpoint));
+            let other_index = Outpoint::new(outpoint.txid, outpoint.vout + 10);
+            assert!(!chain.contains_utxo_ct(&other_index));
+        }
+        let spent = test_util::coinbase_output(&chain.blocks[0]).0;
+        assert!(!chain.contains_utxo_ct(&spent));
+    }
+
+    #[test]
+    fn block_bundles_verify_after_a_round_trip() {
+        let key = PrivateKey::new_key();
+        let chain = chain_with_utxos(&key, 3);
+        assert!(chain.block_bundle(2).is_none());
+        let bundle = chain.block_bundle(1).unwrap();
+        assert_eq!(bundle.transactions.len(), 2);
+        let bytes = util::serialize(&bundle);
+        let mut bundle: BlockBundle =
+            ciborium::from_reader(bytes.as_slice()).unwrap();
+        assert!(bundle.verify());
+        bundle.transactions[1].outputs[0].value = Amount::from_sats(1);
+        assert!(!bundle.verify());
+    }
+
+    #[test]
+    fn smaller_targets_sort_as_harder() {
+        let header = |target: u64| {
+            BlockHeader::new(
+                DateTime::UNIX_EPOCH,
+                0,
+                Hash::zero(),
+                MerkleRoot::calculate(&[Transaction::coinbase(0, vec![])]),
+                U256::from(target),
+            )
+        };
+        let mut headers = [header(10), header(1000), header(100)];
+        headers.sort_by(BlockHeader::cmp_difficulty);
+        let targets: Vec<U256> =
+            headers.iter().map(|header| header.target).collect();
+        assert_eq!(
+            targets,
+            vec![U256::from(1000), U256::from(100), U256::from(10)]
+        );
+        assert_eq!(header(5).cmp_difficulty(&header(5)), Ordering::Equal);
+    }
+
+    #[test]
+    fn from_bytes_decodes_blocks_within_the_limits() {
+        let key = PrivateKey::new_key();
+        let chain = chain_with_utxos(&key, 2);
+        let block = &chain.blocks[1];
+        let bytes = block.to_bytes();
+        let params = test_util::params();
+        let decoded = Block::from_bytes(&bytes, &params).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+
+        let small = ChainParams {
+            max_block_size: bytes.len() - 1,
+            ..test_util::params()
+        };
+        assert!(matches!(
+            Block::from_bytes(&bytes, &small),
+            Err(BtcError::BlockTooLarge)
+        ));
+        let few_transactions = ChainParams {
+            max_transactions_per_block: 1,
+            ..test_util::params()
+        };
+        assert!(matches!(
+            Block::from_bytes(&bytes, &few_transactions),
+            Err(BtcError::BlockTooLarge)
+        ));
+        let few_sigops = ChainParams {
+            max_block_sigops: 0,
+            ..test_util::params()
+        };
+        assert!(matches!(
+            Block::from_bytes(&bytes, &few_sigops),
+            Err(BtcError::TooManySigops)
+        ));
+    }
+
+    #[test]
+    fn from_bytes_rejects_malformed_input_without_panicking() {
+        let params = test_util::params();
+        let block =
+            chain_with_utxos(&PrivateKey::new_key(), 2).blocks[1].to_bytes();
+        for length in 0..block.len() {
+            assert!(Block::from_bytes(&block[..length], &params).is_err());
+        }
+        // a map whose transactions claim to be 2^64 - 1, and a single
+        // transaction claiming as many inputs or outputs
+        let transactions = |transaction: &[u8]| {
+            let mut bytes = vec![0xa1, 0x6c];
+            bytes.extend_from_slice(b"transactions");
+            bytes.extend_from_slice(transaction);
+            bytes
+        };
+        let huge_count = [0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let field = |name: &str| {
+            let mut bytes = vec![0x81, 0xa1, 0x60 + name.len() as u8];
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&huge_count);
+            bytes
+        };
+        assert!(matches!(
+            Block::from_bytes(&transactions(&huge_count), &params),
+            Err(BtcError::BlockTooLarge)
+        ));
+        assert!(matches!(
+            Block::from_bytes(&transactions(&field("inputs")), &params),
+            Err(BtcError::TooManySigops)
+        ));
+        assert!(matches!(
+            Block::from_bytes(&transactions(&field("outputs")), &params),
+            Err(BtcError::BlockTooLarge)
+        ));
+        assert!(matches!(
+            Block::from_bytes(&[0xff; 64], &params),
+            Err(BtcError::InvalidBlockEncoding)
+        ));
+    }
+
+    #[test]
+    fn liquid_supply_ratio_counts_every_claimed_reward() {
+        let empty = Blockchain::with_params(test_util::params());
+        assert_eq!(empty.liquid_supply_ratio(), 0.0);
+        // the fee of the spend goes to the miner, so nothing is lost
+        let chain = chain_with_utxos(&PrivateKey::new_key(), 3);
+        assert_eq!(chain.liquid_supply_ratio(), 1.0);
+    }
+
+    #[test]
+    fn time_locks_are_compared_against_the_median_time_past() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        test_util::mine_blocks(&mut chain, 5, &key.public_key());
+        let tip_time = chain.blocks.last().unwrap().header.timestamp;
+        let mut transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        transaction.lock_time = tip_time.timestamp() as u64;
+        transaction.inputs[0].sequence = 0;
+        transaction.sign_input(0, funds.1, &key).unwrap();
+
+        // the block itself is timestamped after the lock time, but not the
+        // median time past
+        let block = test_util::next_block(
+            &chain,
+            vec![transaction.clone()],
+            &key.public_key(),
+        );
+        assert!(block.header.timestamp > tip_time);
+        assert!(chain.add_block(block).is_err());
+
+        while chain.median_time_past() <= tip_time {
+            test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        }
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn utxos_for_sorted_lists_the_largest_first() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let values = [300, 5000, 1000];
+        let outputs = values
+            .iter()
+            .map(|value| output(*value, &key.public_key()))
+            .collect();
+        let transaction = spend(&key, &[funds], outputs);
+        let txid = transaction.hash();
+        let miner = PrivateKey::new_key().public_key();
+        let block = test_util::next_block(&chain, vec![transaction], &miner);
+        chain.add_block(block).unwrap();
+
+        assert_eq!(
+            chain.utxos_for_sorted(&key.public_key()),
+            vec![
+                (Outpoint::new(txid, 1), Amount::from_sats(5000)),
+                (Outpoint::new(txid, 2), Amount::from_sats(1000)),
+                (Outpoint::new(txid, 0), Amount::from_sats(300)),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_timed_reports_the_phases_and_the_first_error() {
+        let chain = chain_with_utxos(&PrivateKey::new_key(), 2);
+        let (result, timings) = chain.validate_timed();
+        result.unwrap();
+        assert!(timings.signatures > Duration::ZERO);
+
+        let mut tampered = chain.clone();
+        tampered.blocks[1].transactions[1].outputs[0].value =
+            Amount::from_sats(1);
+        let (result, _) = tampered.validate_timed();
+        assert!(matches!(result, Err(BtcError::InvalidMerkleRoot)));
+    }
+
+    #[test]
+    fn block_template_leaves_out_transactions_the_chain_moved_past() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 3);
+        let coins = chain.utxos_for_sorted(&key.public_key());
+        let pay = |coin: (Outpoint, Amount), fee: u64| {
+            let change = coin.1.to_sats() - fee;
+            spend(&key, &[coin], vec![output(change, &key.public_key())])
+        };
+        let mut mempool = Mempool::new();
+        mempool.add(pay(coins[0], 1000), &chain).unwrap();
+        let kept = pay(coins[1], 5000);
+        mempool.add(kept.clone(), &chain).unwrap();
+        // a block spends the first coin differently, and the mempool isn't
+        // told about it
+        let conflict = pay(coins[0], 2000);
+        let block =
+            test_util::next_block(&chain, vec![conflict], &key.public_key());
+        chain.add_block(block).unwrap();
+
+        let miner = PrivateKey::new_key().public_key();
+        let now = chain.blocks.last().unwrap().header.timestamp;
+        chain.set_clock(Arc::new(FixedClock(now)));
+        let template: serde_json::Value = serde_json::from_str(
+            &chain.get_block_template_json(&mempool, &miner),
+        )
+        .unwrap();
+        assert_eq!(template["curtime"], now.timestamp());
+        assert_eq!(template["height"], chain.block_height());
+        let transactions = template["transactions"].as_array().unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0]["txid"], kept.hash().to_string());
+        let reward = chain.params().block_reward(chain.block_height());
+        assert_eq!(template["coinbasevalue"], reward.to_sats() + 5000);
+    }
+
+    #[test]
+    fn is_final_follows_the_lock_time() {
+        let key = PrivateKey::new_key();
+        let funds = (Outpoint::new(Hash::zero(), 0), Amount::from_sats(1000));
+        let mut transaction =
+            spend(&key, &[funds], vec![output(900, &key.public_key())]);
+        let epoch = DateTime::UNIX_EPOCH;
+        assert!(transaction.is_final(0, epoch));
+
+        transaction.inputs[0].sequence = 0;
+        transaction.lock_time = 10;
+        assert!(!transaction.is_final(10, epoch));
+        assert!(transaction.is_final(11, epoch));
+
+        let lock = crate::LOCKTIME_THRESHOLD + 100;
+        transaction.lock_time = lock;
+        let at =
+            |seconds: u64| DateTime::from_timestamp(seconds as i64, 0).unwrap();
+        assert!(!transaction.is_final(u64::MAX, at(lock)));
+        assert!(transaction.is_final(0, at(lock + 1)));
+
+        // inputs with the final sequence opt out of the lock
+        transaction.inputs[0].sequence = crate::SEQUENCE_FINAL;
+        assert!(transaction.is_final(0, epoch));
+    }
+
+    #[test]
+    fn sync_progress_is_the_share_of_the_assumed_work() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        test_util::mine_blocks(&mut chain, 3, &miner);
+        // any hash meets the regtest target, so each block is one hash
+        assert_eq!(chain.total_work(), U256::from(4));
+        assert_eq!(chain.sync_progress(U256::from(8)), 0.5);
+        assert_eq!(chain.sync_progress(U256::from(2)), 1.0);
+
+        let mut header = chain.blocks[0].header.clone();
+        header.target = U256::MAX >> 1;
+        assert_eq!(header.work(), U256::from(2));
+    }
+
+    #[test]
+    fn oversized_transactions_are_not_relayed_but_stay_valid() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let mut transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        transaction.coinbase_data = vec![0; crate::MAX_STANDARD_TX_SIZE];
+        transaction.sign_input(0, funds.1, &key).unwrap();
+        assert!(transaction.serialized_size() > crate::MAX_STANDARD_TX_SIZE);
+
+        let mut mempool = Mempool::new();
+        assert!(matches!(
+            mempool.add(transaction.clone(), &chain),
+            Err(BtcError::NonStandardTransaction)
+        ));
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn utxos_at_height_replays_the_first_blocks() {
+        let chain = chain_with_utxos(&PrivateKey::new_key(), 3);
+        assert!(chain.utxos_at_height(0).is_empty());
+        let genesis = chain.utxos_at_height(1);
+        assert_eq!(genesis.len(), 1);
+        assert!(
+            genesis.contains(&test_util::coinbase_output(&chain.blocks[0]).0)
+        );
+        let tip = chain.utxos_at_height(chain.block_height());
+        assert_eq!(tip.len(), chain.utxos.len());
+        assert!(chain.utxos.iter().all(|(outpoint, output)| tip
+            .get(outpoint)
+            .map(TransactionOutput::hash)
+            == Some(output.hash())));
+    }
+
+    // A block whose transactions after the coinbase are `a`, spending an
+    // outside output into two, `b` spending both, `c` spending `b`, and
+    // `d`, independent.
+    fn chained_block(key: &PrivateKey) -> Block {
+        let pubkey = key.public_key();
+        let outside = |seed: u64| {
+            (
+                Outpoint::new(Hash::hash(&seed), 0),
+                Amount::from_sats(10_000),
+            )
+        };
+        let a = spend(
+            key,
+            &[outside(0)],
+            vec![output(4000, &pubkey), output(4000, &pubkey)],
+        );
+        let a_outputs = [0, 1].map(|vout| {
+            (Outpoint::new(a.hash(), vout), Amount::from_sats(4000))
+        });
+        let b = spend(key, &a_outputs, vec![output(7000, &pubkey)]);
+        let b_output = (Outpoint::new(b.hash(), 0), Amount::from_sats(7000));
+        let c = spend(key, &[b_output], vec![output(6000, &pubkey)]);
+        let d = spend(key, &[outside(1)], vec![output(9000, &pubkey)]);
+        let coinbase = Transaction::coinbase(1, vec![output(1, &pubkey)]);
+        let transactions = vec![coinbase, a, b, c, d];
+        let header = BlockHeader::new(
+            DateTime::UNIX_EPOCH,
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            U256::MAX,
+        );
+        Block::new(header, transactions)
+    }
+
+    #[test]
+    fn dependency_edges_link_spenders_to_in_block_parents() {
+        let block = chained_block(&PrivateKey::new_key());
+        let txid = |index: usize| block.transactions[index].hash();
+        // b spends two outputs of a but depends on it once
+        assert_eq!(
+            block.dependency_edges(),
+            vec![(txid(2), txid(1)), (txid(3), txid(2))]
+        );
+    }
+
+    #[test]
+    fn coinbases_must_commit_to_their_height() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let block = test_util::next_block(&chain, vec![], &miner);
+        assert_eq!(block.transactions[0].coinbase_height(), Some(1));
+
+        let mut wrong = block.clone();
+        wrong.transactions[0].coinbase_data = 2u64.to_le_bytes().to_vec();
+        wrong.refresh_merkle_root();
+        assert!(wrong.header.mine(u64::MAX));
+        assert!(chain.add_block(wrong).is_err());
+        let mut missing = block.clone();
+        missing.transactions[0].coinbase_data = vec![1];
+        missing.refresh_merkle_root();
+        assert!(missing.header.mine(u64::MAX));
+        assert!(chain.add_block(missing).is_err());
+
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn is_unspent_follows_spends() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        assert!(chain.is_unspent(&funds.0));
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        let created = Outpoint::new(transaction.hash(), 0);
+        assert!(!chain.is_unspent(&created));
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+        assert!(!chain.is_unspent(&funds.0));
+        assert!(chain.is_unspent(&created));
+    }
+
+    #[test]
+    fn historical_fee_survives_the_inputs_being_spent() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let first = spend(
+            &key,
+            &[funds],
+            vec![output(funds.1.to_sats() - 700, &key.public_key())],
+        );
+        let change = (Outpoint::new(first.hash(), 0), first.outputs[0].value);
+        let second =
+            spend(&key, &[change], vec![output(1000, &key.public_key())]);
+        for transaction in [first.clone(), second] {
+            let block = test_util::next_block(
+                &chain,
+                vec![transaction],
+                &key.public_key(),
+            );
+            chain.add_block(block).unwrap();
+        }
+        assert_eq!(
+            chain.historical_fee(&first.hash()).unwrap(),
+            Amount::from_sats(700)
+        );
+        assert_eq!(chain.historical_fee(&funds.0.txid).unwrap(), Amount::ZERO);
+        assert!(matches!(
+            chain.historical_fee(&Hash::zero()),
+            Err(BtcError::TransactionNotFound)
+        ));
+    }
+
+    #[test]
+    fn coinbases_may_split_their_value_across_outputs() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let mut block =
+            test_util::next_block(&chain, vec![], &key.public_key());
+        let value = block.transactions[0].outputs[0].value.to_sats();
+        block.transactions[0].outputs = (0..5)
+            .map(|_| output(value / 5, &key.public_key()))
+            .collect();
+        block.refresh_merkle_root();
+        assert!(block.header.mine(u64::MAX));
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn miner_fees_of_overspending_transactions_are_an_error() {
+        let key = PrivateKey::new_key();
+        let chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let overspending = spend(
+            &key,
+            &[funds],
+            vec![output(funds.1.to_sats() + 1, &key.public_key())],
+        );
+        let block = test_util::next_block(
+            &chain,
+            vec![overspending],
+            &key.public_key(),
+        );
+        assert!(matches!(
+            block.calculate_miner_fees(&chain.utxos),
+            Err(BtcError::InvalidTransaction)
+        ));
+    }
+
+    #[test]
+    fn listeners_are_told_about_disconnected_blocks() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let recorder = Arc::new(test_util::Recorder::default());
+        chain.add_listener(recorder.clone());
+        test_util::mine_blocks(&mut chain, 2, &miner);
+        let hashes: Vec<Hash> = chain.iter_blocks().map(Block::hash).collect();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Connected(1, hashes[1]),
+                Event::Connected(2, hashes[2])
+            ]
+        );
+        chain.disconnect_tip().unwrap();
+        chain.disconnect_tip().unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Disconnected(2, hashes[2]),
+                Event::Disconnected(1, hashes[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_against_any_accepts_a_transaction_valid_on_one_tip() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let before = chain.utxos.clone();
+        // this tip already spent the funds
+        let spent = spend(&key, &[funds], vec![output(5, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![spent], &key.public_key());
+        chain.add_block(block).unwrap();
+
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        assert!(transaction.verify_against_any(&[&chain.utxos, &before]));
+        assert!(!transaction.verify_against_any(&[&chain.utxos]));
+        assert!(!transaction.verify_against_any(&[]));
+    }
+
+    #[test]
+    fn blocks_must_be_timestamped_after_the_median_time_past() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        test_util::mine_blocks(&mut chain, 4, &miner);
+        let median_time_past = chain.median_time_past();
+        // far behind the median time past, or right at it
+        for timestamp in [
+            median_time_past - chrono::Duration::hours(3),
+            median_time_past,
+        ] {
+            let mut block = test_util::next_block(&chain, vec![], &miner);
+            block.header.timestamp = timestamp;
+            assert!(block.header.mine(u64::MAX));
+            assert!(chain.add_block(block).is_err());
+        }
+        let mut block = test_util::next_block(&chain, vec![], &miner);
+        block.header.timestamp =
+            median_time_past + chrono::Duration::seconds(1);
+        assert!(block.header.mine(u64::MAX));
+        chain.add_block(block).unwrap();
+    }
+
+    // A chain whose second block holds a transaction paying `values` to
+    // `key`, and whose txid is returned. The rest of the genesis coinbase
+    // goes to the miner as fee.
+    fn chain_paying(key: &PrivateKey, values: &[u64]) -> (Blockchain, Hash) {
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let outputs = values
+            .iter()
+            .map(|value| output(*value, &key.public_key()))
+            .collect();
+        let transaction = spend(key, &[funds], outputs);
+        let txid = transaction.hash();
+        let miner = PrivateKey::new_key().public_key();
+        let block = test_util::next_block(&chain, vec![transaction], &miner);
+        chain.add_block(block).unwrap();
+        (chain, txid)
+    }
+
+    #[test]
+    fn utxo_value_entropy_buckets_values_by_magnitude() {
+        // the miner's coinbase is in a bucket of its own
+        let (chain, _) = chain_paying(&PrivateKey::new_key(), &[1000, 1020]);
+        let expected = -(2.0 / 3.0 * f64::log2(2.0 / 3.0)
+            + 1.0 / 3.0 * f64::log2(1.0 / 3.0));
+        assert!((chain.utxo_value_entropy() - expected).abs() < 1e-9);
+
+        let (chain, _) = chain_paying(&PrivateKey::new_key(), &[1, 4, 1000]);
+        assert!((chain.utxo_value_entropy() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pruned_chains_still_validate_spends_and_maturity() {
+        let key = PrivateKey::new_key();
+        let params = ChainParams {
+            coinbase_maturity: 3,
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        test_util::mine_blocks(&mut chain, 3, &key.public_key());
+        let old = test_util::coinbase_output(&chain.blocks[0]);
+        let young = test_util::coinbase_output(&chain.blocks[3]);
+        chain.prune(1);
+        assert!(chain.blocks[..3]
+            .iter()
+            .all(|block| block.transactions.is_empty()));
+
+        // the coinbase at height 3 matures at 6, the genesis one at 3
+        let young_spend =
+            spend(&key, &[young], vec![output(1000, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![young_spend], &key.public_key());
+        assert!(chain.add_block(block).is_err());
+        assert_eq!(chain.coinbase_maturity_remaining(&young.0), Some(2));
+        let old_spend =
+            spend(&key, &[old], vec![output(1000, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![old_spend], &key.public_key());
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn common_denominations_count_every_output_ever_created() {
+        let key = PrivateKey::new_key();
+        let (mut chain, txid) =
+            chain_paying(&key, &[1000, 1000, 1000, 500, 500, 7]);
+        // spending outputs doesn't forget them
+        let spent = (Outpoint::new(txid, 0), Amount::from_sats(1000));
+        let transaction =
+            spend(&key, &[spent], vec![output(500, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+
+        assert_eq!(
+            chain.common_denominations(2),
+            vec![(Amount::from_sats(500), 3), (Amount::from_sats(1000), 3)]
+        );
+    }
+
+    #[test]
+    fn refresh_merkle_root_patches_the_header() {
+        let miner = PrivateKey::new_key().public_key();
+        let chain = test_util::chain(&miner);
+        let mut block = test_util::next_block(&chain, vec![], &miner);
+        block.verify_merkle_root().unwrap();
+        block.transactions[0].outputs.push(output(0, &miner));
+        assert!(matches!(
+            block.verify_merkle_root(),
+            Err(BtcError::InvalidMerkleRoot)
+        ));
+        block.refresh_merkle_root();
+        block.verify_merkle_root().unwrap();
+    }
+
+    #[test]
+    fn verify_linkage_catches_reordered_blocks() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        test_util::mine_blocks(&mut chain, 3, &miner);
+        chain.prune(0);
+        chain.verify_linkage().unwrap();
+        chain.blocks.swap(1, 2);
+        assert!(chain.verify_linkage().is_err());
+    }
+
+    #[test]
+    fn headers_first_sync_connects_bodies_filled_out_of_order() {
+        let key = PrivateKey::new_key();
+        let mut source = test_util::chain(&key.public_key());
+        test_util::mine_blocks(&mut source, 1, &key.public_key());
+        let funds = test_util::coinbase_output(&source.blocks[1]);
+        let transaction = spend(
+            &key,
+            &[funds],
+            vec![
+                output(1000, &key.public_key()),
+                output(2000, &key.public_key()),
+            ],
+        );
+        let block = test_util::next_block(
+            &source,
+            vec![transaction],
+            &key.public_key(),
+        );
+        source.add_block(block).unwrap();
+        test_util::mine_blocks(&mut source, 1, &key.public_key());
+
+        let mut chain = Blockchain::with_params(test_util::params());
+        chain.add_block(source.blocks[0].clone()).unwrap();
+        let headers = source.blocks[1..]
+            .iter()
+            .map(|block| block.header.clone())
+            .collect();
+        chain.import_headers(headers).unwrap();
+        assert_eq!(chain.block_height(), source.block_height());
+        let body = |height: usize| source.blocks[height].transactions.clone();
+        // a body not matching its header is refused
+        assert!(chain.fill_body(2, body(1)).is_err());
+
+        chain.fill_body(3, body(3)).unwrap();
+        let next = test_util::next_block(&source, vec![], &key.public_key());
+        assert!(chain.add_block(next.clone()).is_err());
+        chain.fill_body(1, body(1)).unwrap();
+        chain.fill_body(2, body(2)).unwrap();
+        assert_eq!(chain.utxo_commitment(), source.utxo_commitment());
+        chain.add_block(next).unwrap();
+    }
+
+    #[test]
+    fn early_coinbases_wait_for_the_minimum_spend_height() {
+        let key = PrivateKey::new_key();
+        let params = ChainParams {
+            min_coinbase_spend_height: 3,
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        while chain.block_height() < 3 {
+            assert_eq!(
+                chain.coinbase_maturity_remaining(&funds.0),
+                Some(3 - chain.block_height())
+            );
+            let block = test_util::next_block(
+                &chain,
+                vec![transaction.clone()],
+                &key.public_key(),
+            );
+            assert!(chain.add_block(block).is_err());
+            test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        }
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn luck_is_how_far_below_the_target_the_hash_is() {
+        let miner = PrivateKey::new_key().public_key();
+        let chain = test_util::chain(&miner);
+        let mut block = test_util::next_block(&chain, vec![], &miner);
+        block.header.target = U256::MAX >> 4;
+        assert!(block.header.mine(u64::MAX));
+        let hash = U256::from_little_endian(&block.hash().as_bytes());
+        let expected =
+            util::u256_to_f64(block.header.target) / util::u256_to_f64(hash);
+        let luck = block.luck(U256::MAX);
+        assert!(luck >= 1.0);
+        assert!((luck - expected).abs() < expected * 1e-9);
+        // the reference difficulty cancels out
+        assert!((block.luck(U256::MAX >> 8) - luck).abs() < luck * 1e-9);
+    }
+
+    #[test]
+    fn tip_age_is_measured_from_the_tip_timestamp() {
+        let miner = PrivateKey::new_key().public_key();
+        let empty = Blockchain::with_params(test_util::params());
+        let now = DateTime::from_timestamp(1_000, 0).unwrap();
+        assert_eq!(empty.tip_age(now).num_seconds(), 1_000);
+        let chain = test_util::chain(&miner);
+        let tip = chain.blocks[0].header.timestamp;
+        let later = tip + chrono::Duration::seconds(90);
+        assert_eq!(chain.tip_age(later).num_seconds(), 90);
+        let earlier = tip - chrono::Duration::seconds(5);
+        assert_eq!(chain.tip_age(earlier).num_seconds(), -5);
+    }
+
+    #[test]
+    fn external_proofs_are_checked_against_stored_headers() {
+        let (mut chain, txid) =
+            chain_paying(&PrivateKey::new_key(), &[1000, 2000]);
+        let block = chain.blocks[1].clone();
+        let transaction = block.transactions[1].clone();
+        assert_eq!(transaction.hash(), txid);
+        let proof = MerkleRoot::proof(&block.transactions, 1).unwrap();
+        let wrong_proof = MerkleRoot::proof(&block.transactions, 0).unwrap();
+        chain.prune(0);
+
+        chain
+            .verify_external_proof(&block.hash(), &transaction, &proof)
+            .unwrap();
+        assert!(matches!(
+            chain.verify_external_proof(
+                &block.hash(),
+                &transaction,
+                &wrong_proof
+            ),
+            Err(BtcError::InvalidMerkleRoot)
+        ));
+        assert!(matches!(
+            chain.verify_external_proof(&Hash::zero(), &transaction, &proof),
+            Err(BtcError::InvalidHash)
+        ));
+    }
+
+    #[test]
+    fn autocheckpoints_save_the_chain_every_few_blocks() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let path = test_util::temp_path();
+        chain.set_autocheckpoint(2, path.clone());
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        let saved = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(saved.block_height(), 2);
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        let saved = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(saved.block_height(), 2);
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        let saved = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(saved.block_height(), 4);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn signatures_only_commit_to_the_outputs_of_their_sighash_type() {
+        let key = PrivateKey::new_key();
+        let (chain, txid) = chain_paying(&key, &[1000, 2000]);
+        let inputs = [
+            (Outpoint::new(txid, 0), Amount::from_sats(1000)),
+            (Outpoint::new(txid, 1), Amount::from_sats(2000)),
+        ];
+        let pubkey = key.public_key();
+        let signed = |sighash_type: SighashType| {
+            let mut transaction = spend(
+                &key,
+                &inputs,
+                vec![output(1000, &pubkey), output(1500, &pubkey)],
+            );
+            for (index, (_, value)) in inputs.iter().enumerate() {
+                transaction.inputs[index].sighash_type = sighash_type;
+                transaction.sign_input(index, *value, &key).unwrap();
+            }
+            transaction.verify(&chain.utxos).unwrap();
+            transaction
+        };
+        let changing_output = |transaction: &Transaction, index: usize| {
+            let mut changed = transaction.clone();
+            changed.outputs[index].value = Amount::from_sats(10);
+            changed.verify(&chain.utxos).is_ok()
+        };
+
+        let all = signed(SighashType::All);
+        assert!(!changing_output(&all, 0));
+        assert!(!changing_output(&all, 1));
+        let none = signed(SighashType::None);
+        assert!(changing_output(&none, 0));
+        assert!(changing_output(&none, 1));
+        // each input commits to its own output, and both are signed
+        let single = signed(SighashType::Single);
+        assert!(!changing_output(&single, 0));
+        assert!(!changing_output(&single, 1));
+        let mut partly_single = single.clone();
+        partly_single.inputs[1].sighash_type = SighashType::None;
+        partly_single.sign_input(1, inputs[1].1, &key).unwrap();
+        assert!(changing_output(&partly_single, 1));
+        assert!(!changing_output(&partly_single, 0));
+    }
+
+    #[test]
+    fn add_block_validates_the_genesis_and_later_blocks() {
+        let key = PrivateKey::new_key();
+        let params = test_util::params();
+        let genesis = Block::genesis(&params, key.public_key()).unwrap();
+        let mut chain = Blockchain::with_params(params.clone());
+        let mut overpaying = genesis.clone();
+        overpaying.transactions[0].outputs[0].value = Amount::from_sats(
+            genesis.transactions[0].outputs[0].value.to_sats() + 1,
+        );
+        overpaying.refresh_merkle_root();
+        assert!(overpaying.header.mine(u64::MAX));
+        assert!(chain.add_block(overpaying).is_err());
+        chain.add_block(genesis).unwrap();
+
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        // a tampered output breaks both the merkle root and the signature
+        let mut tampered = block.clone();
+        tampered.transactions[1].outputs[0].value = Amount::from_sats(2000);
+        assert!(chain.add_block(tampered.clone()).is_err());
+        tampered.refresh_merkle_root();
+        assert!(tampered.header.mine(u64::MAX));
+        assert!(chain.add_block(tampered).is_err());
+        chain.add_block(block).unwrap();
+        assert_eq!(chain.block_height(), 2);
+    }
+
+    #[test]
+    fn address_reuse_counts_spent_and_unspent_outputs() {
+        let key = PrivateKey::new_key();
+        // the genesis coinbase, spent since, was paid to `key` too
+        let (chain, _) = chain_paying(&key, &[1000, 2000, 3000]);
+        let counts = chain.address_reuse_count();
+        assert_eq!(counts[&key.public_key()], 4);
+        // and the miner of the second block received a single output
+        assert_eq!(counts.len(), 2);
+        assert!(counts.values().any(|count| *count == 1));
+    }
+
+    #[test]
+    fn mine_with_extranonce_rolls_the_coinbase_over() {
+        let miner = PrivateKey::new_key().public_key();
+        let chain = test_util::chain(&miner);
+        let extra_nonce = |block: &Block, offset: usize| {
+            let data = &block.transactions[0].coinbase_data;
+            u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+        };
+        // a block whose last nonce doesn't meet the target, so mining it
+        // from there has to bump the extra nonce
+        let exhausted = |coinbase_data: Option<Vec<u8>>| loop {
+            let mut block = test_util::next_block(&chain, vec![], &miner);
+            if let Some(data) = &coinbase_data {
+                block.transactions[0].coinbase_data = data.clone();
+                block.refresh_merkle_root();
+            }
+            block.header.target = U256::MAX >> 4;
+            block.header.nonce = u64::MAX;
+            if !block.hash().matches_target(block.header.target) {
+                return block;
+            }
+        };
+
+        let mut block = exhausted(None);
+        assert!(!block.mine_with_extranonce(0));
+        assert!(block.mine_with_extranonce(1));
+        assert_eq!(extra_nonce(&block, 8), 1);
+        block.check().unwrap();
+        assert_eq!(block.transactions[0].coinbase_height(), Some(1));
+
+        // shorter coinbase data is kept, the extra nonce goes after it
+        let mut block = exhausted(Some(vec![1, 2, 3]));
+        assert!(block.mine_with_extranonce(1));
+        let data = &block.transactions[0].coinbase_data;
+        assert_eq!(data.len(), 11);
+        assert_eq!(data[..3], [1, 2, 3]);
+        assert_eq!(extra_nonce(&block, 3), 1);
+
+        let mut empty = Block::new(block.header.clone(), vec![]);
+        empty.header.target = U256::zero();
+        empty.header.nonce = u64::MAX;
+        assert!(!empty.mine_with_extranonce(1));
+    }
+
+    #[test]
+    fn rebuilt_utxos_keep_every_output_spendable() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 2);
+        let before = chain.utxo_commitment();
+        chain.rebuild_utxos();
+        assert_eq!(chain.utxo_commitment(), before);
+
+        let txid = chain.blocks[1].transactions[1].hash();
+        let value = chain.blocks[1].transactions[1].outputs[0].value;
+        let inputs = [
+            (Outpoint::new(txid, 0), value),
+            (Outpoint::new(txid, 1), value),
+        ];
+        let sats = value.to_sats() * 2 - 1000;
+        let transaction =
+            spend(&key, &inputs, vec![output(sats, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+        assert!(inputs
+            .iter()
+            .all(|(outpoint, _)| !chain.is_unspent(outpoint)));
+    }
+
+    #[test]
+    fn mine_finds_a_nonce_for_an_easy_target() {
+        let mut header = BlockHeader::new(
+            Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&[Transaction::coinbase(0, vec![])]),
+            U256::MAX >> 8,
+        );
+        assert!(header.mine(u64::MAX));
+        assert!(header.hash().matches_target(header.target));
+        let nonce = header.nonce;
+        // the winning nonce is kept when mining again
+        assert!(header.mine(u64::MAX));
+        assert_eq!(header.nonce, nonce);
+
+        header.target = U256::zero();
+        header.nonce = 2;
+        assert!(!header.mine(5));
+        assert_eq!(header.nonce, 5);
+    }
+
+    #[test]
+    fn header_size_only_depends_on_the_integer_widths() {
+        let header = |nonce: u64, prev_block_hash: Hash| {
+            BlockHeader::new(
+                DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                nonce,
+                prev_block_hash,
+                MerkleRoot::calculate(&[Transaction::coinbase(0, vec![])]),
+                U256::MAX,
+            )
+        };
+        let a = header(1, Hash::hash(&1));
+        let b = header(2, Hash::hash(&2));
+        assert_eq!(a.serialized_size(), util::serialize(&a).len());
+        assert_eq!(a.serialized_size(), b.serialized_size());
+        // CBOR stores small integers in the initial byte, u64::MAX takes
+        // eight more
+        let c = header(u64::MAX, Hash::hash(&1));
+        assert_eq!(c.serialized_size(), a.serialized_size() + 8);
+    }
+
+    #[test]
+    fn slow_blocks_make_the_next_target_easier() {
+        let params = ChainParams {
+            min_target: U256::MAX >> 2,
+            difficulty_update_interval: 4,
+            ..test_util::params()
+        };
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain =
+            Blockchain::new_with_genesis(params.clone(), miner.clone())
+                .unwrap();
+        let start = chain.blocks[0].header.timestamp;
+        let add_block = |chain: &mut Blockchain, seconds: i64| {
+            let mut block = test_util::next_block(chain, vec![], &miner);
+            block.header.timestamp = start + chrono::Duration::seconds(seconds);
+            block.header.nonce = 0;
+            assert!(block.header.mine(u64::MAX));
+            chain.add_block(block).unwrap();
+        };
+
+        // too few blocks to retarget yet
+        for seconds in 1..3 {
+            add_block(&mut chain, seconds);
+            assert_eq!(chain.next_target(), params.min_target);
+        }
+        add_block(&mut chain, 3);
+        let harder = chain.next_target();
+        assert!(harder < params.min_target);
+
+        // 4 blocks 100 seconds apart instead of 10: 4 times easier at most
+        for seconds in [100, 200, 300, 400] {
+            add_block(&mut chain, seconds);
+        }
+        assert_eq!(chain.next_target(), harder / 30 * 120);
+    }
+
+    #[test]
+    fn saved_chains_load_back_with_their_utxos() {
+        let key = PrivateKey::new_key();
+        let chain = chain_with_utxos(&key, 2);
+        let path = test_util::temp_path();
+        chain.save_to_file(&path).unwrap();
+        let mut loaded = Blockchain::load_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.block_height(), chain.block_height());
+        assert!(loaded
+            .iter_blocks()
+            .zip(chain.iter_blocks())
+            .all(|(a, b)| a.hash() == b.hash()));
+        assert_eq!(loaded.utxo_commitment(), chain.utxo_commitment());
+        assert_eq!(loaded.total_work(), chain.total_work());
+        test_util::mine_blocks(&mut loaded, 1, &key.public_key());
+
+        assert!(matches!(
+            Blockchain::load_from_file(&path),
+            Err(BtcError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn non_standard_transactions_are_only_kept_out_of_the_mempool() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let dust = crate::DUST_THRESHOLD.to_sats() - 1;
+        let transaction = spend(
+            &key,
+            &[funds],
+            vec![
+                output(dust, &key.public_key()),
+                output(1000, &key.public_key()),
+            ],
+        );
+        let policy = StandardnessPolicy::default();
+        assert!(!transaction.is_standard(&policy));
+        let relaxed = StandardnessPolicy {
+            dust_threshold: Amount::from_sats(dust),
+            ..policy.clone()
+        };
+        assert!(transaction.is_standard(&relaxed));
+        let single_output = StandardnessPolicy {
+            max_outputs: 1,
+            ..relaxed
+        };
+        assert!(!transaction.is_standard(&single_output));
+
+        let mut strict = Mempool::with_policy(policy);
+        assert!(matches!(
+            strict.add(transaction.clone(), &chain),
+            Err(BtcError::NonStandardTransaction)
+        ));
+        Mempool::new().add(transaction.clone(), &chain).unwrap();
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn pending_balances_include_mempool_spends_and_receipts() {
+        let key = PrivateKey::new_key();
+        let other = PrivateKey::new_key().public_key();
+        let chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let change = funds.1.to_sats() - 3000;
+        let transaction = spend(
+            &key,
+            &[funds],
+            vec![output(2000, &other), output(change, &key.public_key())],
+        );
+        let mut mempool = Mempool::new();
+        assert_eq!(
+            chain.balance_with_mempool(&key.public_key(), &mempool),
+            (funds.1, funds.1)
+        );
+        mempool.add(transaction, &chain).unwrap();
+        assert_eq!(
+            chain.balance_with_mempool(&key.public_key(), &mempool),
+            (funds.1, Amount::from_sats(change))
+        );
+        assert_eq!(
+            chain.balance_with_mempool(&other, &mempool),
+            (Amount::ZERO, Amount::from_sats(2000))
+        );
+    }
+
+    #[test]
+    fn pending_balances_saturate_instead_of_overflowing() {
+        let key = PrivateKey::new_key();
+        let other = PrivateKey::new_key().public_key();
+        let max = Amount::from_sats(u64::MAX);
+        let params = ChainParams {
+            initial_reward: max,
+            max_money: max,
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let mut mempool = Mempool::new();
+        for block in &chain.blocks {
+            let funds = test_util::coinbase_output(block);
+            let transaction = spend(&key, &[funds], vec![output(0, &other)]);
+            mempool.add(transaction, &chain).unwrap();
+        }
+        // the two coinbases are worth twice the largest amount
+        assert_eq!(
+            chain.balance_with_mempool(&key.public_key(), &mempool),
+            (max, Amount::ZERO)
+        );
+    }
+
+    #[test]
+    fn average_transactions_per_block_over_the_last_blocks() {
+        let key = PrivateKey::new_key();
+        // 1, 2 and 1 transactions
+        let mut chain = chain_with_utxos(&key, 2);
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        assert_eq!(chain.avg_transactions_per_block(2, true), 1.5);
+        assert_eq!(chain.avg_transactions_per_block(2, false), 0.5);
+        assert_eq!(chain.avg_transactions_per_block(10, true), 4.0 / 3.0);
+        assert_eq!(chain.avg_transactions_per_block(0, true), 0.0);
+    }
+
+    #[test]
+    fn sighash_preimages_follow_the_documented_layout() {
+        let key = PrivateKey::new_key();
+        let signature = Signature::sign_output(&Hash::zero(), &key);
+        let mut input = TransactionInput::new(
+            Outpoint::new(Hash::from_bytes([0x11; 32]), 2),
+            signature,
+        );
+        input.sequence = 7;
+        let mut transaction = Transaction::new(
+            vec![input],
+            vec![TransactionOutput {
+                value: Amount::from_sats(0x0102),
+                unique_id: Uuid::from_bytes([0x22; 16]),
+                pubkey: key.public_key(),
+            }],
+        );
+        transaction.lock_time = 5;
+
+        let mut expected = vec![2, 0, 0, 0, 1, 0, 0, 0];
+        expected.extend([0x11; 32]);
+        expected.extend([2, 0, 0, 0, 7, 0, 0, 0]);
+        expected.extend([0, 0, 0, 0]);
+        expected.extend([0xe8, 0x03, 0, 0, 0, 0, 0, 0]);
+        expected.push(1);
+        expected.extend([1, 0, 0, 0]);
+        expected.extend([0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+        expected.extend([0x22; 16]);
+        expected.extend(key.public_key().to_compressed_bytes());
+        expected.extend([5, 0, 0, 0, 0, 0, 0, 0]);
+        let value = Amount::from_sats(1000);
+        assert_eq!(
+            transaction.sighash_preimage(0, value, SighashType::All),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            transaction.sighash(0, value, SighashType::All),
+            Some(Hash::digest(&expected))
+        );
+        assert!(transaction.sighash(1, value, SighashType::All).is_none());
+    }
+
+    #[test]
+    fn descendants_follow_spends_transitively() {
+        let block = chained_block(&PrivateKey::new_key());
+        let txid = |index: usize| block.transactions[index].hash();
+        assert_eq!(block.descendants_of(&txid(1)), vec![txid(2), txid(3)]);
+        assert_eq!(block.descendants_of(&txid(2)), vec![txid(3)]);
+        assert!(block.descendants_of(&txid(3)).is_empty());
+        assert!(block.descendants_of(&txid(4)).is_empty());
+    }
+
+    #[test]
+    fn blocks_at_the_size_limits_pass_and_over_them_fail() {
+        let key = PrivateKey::new_key();
+        let params = ChainParams {
+            max_transactions_per_block: 1,
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+
+        let size = block.serialized_size();
+        let at_limit = ChainParams {
+            max_block_size: size,
+            max_transactions_per_block: 2,
+            ..test_util::params()
+        };
+        block.verify_size(&at_limit).unwrap();
+        let too_small = ChainParams {
+            max_block_size: size - 1,
+            ..at_limit.clone()
+        };
+        assert!(matches!(
+            block.verify_size(&too_small),
+            Err(BtcError::BlockTooLarge)
+        ));
+        let too_few = ChainParams {
+            max_transactions_per_block: 1,
+            ..at_limit
+        };
+        assert!(matches!(
+            block.verify_size(&too_few),
+            Err(BtcError::BlockTooLarge)
+        ));
+
+        // the chain only takes one transaction per block
+        assert!(matches!(
+            chain.add_block(block),
+            Err(BtcError::BlockTooLarge)
+        ));
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+    }
+
+    #[test]
+    fn chainstate_memory_grows_with_the_utxo_set() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let mut last = chain.chainstate_memory();
+        assert!(last > 0);
+        for _ in 0..3 {
+            test_util::mine_blocks(&mut chain, 1, &key.public_key());
+            let memory = chain.chainstate_memory();
+            assert!(memory > last);
+            last = memory;
+        }
+        let split = chain_with_utxos(&key, 4);
+        assert!(split.chainstate_memory() > last);
+
+        // the indexes are counted too, so dropping the undo data shrinks
+        // the estimate even though the UTXO set stays the same
+        let utxos = chain.utxos.len()
+            * (std::mem::size_of::<Outpoint>() + std::mem::size_of::<Coin>());
+        assert!(last > utxos);
+        chain.prune(0);
+        assert!(chain.chainstate_memory() < last);
+    }
+
+    #[test]
+    fn chain_tips_list_the_active_tip_and_the_forks() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let mut fork = chain.clone();
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        let other = PrivateKey::new_key().public_key();
+        test_util::mine_blocks(&mut fork, 2, &other);
+        let tip = |chain: &Blockchain| chain.blocks.last().unwrap().hash();
+        let active = tip(&chain);
+
+        // as much work as the active chain isn't enough to switch
+        chain.add_block(fork.blocks[1].clone()).unwrap();
+        let tips = chain.chain_tips();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].hash, active);
+        assert_eq!(tips[0].status, ChainTipStatus::Active);
+        assert_eq!(tips[1].hash, fork.blocks[1].hash());
+        assert_eq!(tips[1].status, ChainTipStatus::ValidFork);
+        assert_eq!((tips[0].height, tips[1].height), (1, 1));
+
+        chain.add_block(fork.blocks[2].clone()).unwrap();
+        let tips = chain.chain_tips();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].hash, tip(&fork));
+        assert_eq!(tips[0].status, ChainTipStatus::Active);
+        assert_eq!(tips[0].height, 2);
+        assert_eq!(tips[1].hash, active);
+        assert_eq!(tips[1].status, ChainTipStatus::ValidFork);
+        assert!(tips[0].work > tips[1].work);
+    }
+
+    #[test]
+    fn time_based_sequence_locks_wait_for_the_median_time_past() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[1]);
+        let mut transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        // one unit of 512 seconds
+        transaction.inputs[0].sequence = crate::SEQUENCE_LOCKTIME_TYPE_FLAG | 1;
+        transaction.sign_input(0, funds.1, &key).unwrap();
+        let origin_time = chain.median_time_past_at(1);
+
+        let mut locked_blocks = 0;
+        while chain.verify_transaction(&transaction).is_err() {
+            let elapsed = chain.median_time_past() - origin_time;
+            assert!(elapsed.num_seconds() < 512);
+            let block = test_util::next_block(
+                &chain,
+                vec![transaction.clone()],
+                &key.public_key(),
+            );
+            assert!(chain.add_block(block).is_err());
+            test_util::mine_blocks(&mut chain, 1, &key.public_key());
+            locked_blocks += 1;
+        }
+        // blocks are 10 seconds apart, but the median time past lags behind
+        assert!(locked_blocks > 51);
+        let elapsed = chain.median_time_past() - origin_time;
+        assert!(elapsed.num_seconds() >= 512);
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn coinbase_maturity_counts_down_as_blocks_are_added() {
+        let key = PrivateKey::new_key();
+        let params = ChainParams {
+            coinbase_maturity: 3,
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        let (outpoint, _) = test_util::coinbase_output(&chain.blocks[0]);
+        assert_eq!(chain.coinbase_maturity_remaining(&outpoint), Some(2));
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        assert_eq!(chain.coinbase_maturity_remaining(&outpoint), Some(1));
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        assert_eq!(chain.coinbase_maturity_remaining(&outpoint), None);
+
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        let txid = transaction.hash();
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        chain.add_block(block).unwrap();
+        // spent, and not a coinbase
+        assert_eq!(chain.coinbase_maturity_remaining(&outpoint), None);
+        let change = Outpoint::new(txid, 0);
+        assert!(chain.is_unspent(&change));
+        assert_eq!(chain.coinbase_maturity_remaining(&change), None);
+    }
+
+    #[test]
+    fn blocks_must_use_the_expected_target() {
+        let params = ChainParams {
+            min_target: U256::MAX >> 2,
+            difficulty_update_interval: 2,
+            ..test_util::params()
+        };
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain =
+            Blockchain::new_with_genesis(params.clone(), miner.clone())
+                .unwrap();
+        let start = chain.blocks[0].header.timestamp;
+        let block_at = |chain: &Blockchain, seconds: i64, target: U256| {
+            let mut block = test_util::next_block(chain, vec![], &miner);
+            block.header.timestamp = start + chrono::Duration::seconds(seconds);
+            block.header.target = target;
+            block.header.nonce = 0;
+            assert!(block.header.mine(u64::MAX));
+            block
+        };
+        // even a harder target than required is rejected
+        let harder = block_at(&chain, 5, params.min_target >> 1);
+        assert!(chain.add_block(harder).is_err());
+        chain
+            .add_block(block_at(&chain, 5, params.min_target))
+            .unwrap();
+
+        // 2 blocks 5 seconds apart instead of 10: twice as hard
+        let target = chain.next_target();
+        assert_eq!(target, params.min_target / 10 * 5);
+        let stale = block_at(&chain, 10, params.min_target);
+        assert!(chain.add_block(stale).is_err());
+        chain.add_block(block_at(&chain, 10, target)).unwrap();
+    }
+
+    #[test]
+    fn heavier_branches_replace_the_active_chain() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let mut fork = chain.clone();
+        test_util::mine_blocks(&mut chain, 2, &miner);
+        test_util::mine_blocks(
+            &mut fork,
+            3,
+            &PrivateKey::new_key().public_key(),
+        );
+        let recorder = Arc::new(test_util::Recorder::default());
+        chain.add_listener(recorder.clone());
+        let old: Vec<Hash> = chain.iter_blocks().map(Block::hash).collect();
+        let new: Vec<Hash> = fork.iter_blocks().map(Block::hash).collect();
+        let (stale_coinbase, _) = test_util::coinbase_output(&chain.blocks[1]);
+
+        for block in &fork.blocks[1..3] {
+            chain.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(chain.blocks.last().unwrap().hash(), old[2]);
+        assert!(recorder.take().is_empty());
+
+        chain.add_block(fork.blocks[3].clone()).unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                Event::Disconnected(2, old[2]),
+                Event::Disconnected(1, old[1]),
+                Event::Connected(1, new[1]),
+                Event::Connected(2, new[2]),
+                Event::Connected(3, new[3]),
+            ]
+        );
+        assert_eq!(chain.block_height(), 4);
+        assert_eq!(chain.total_work(), fork.total_work());
+        assert_eq!(chain.utxo_commitment(), fork.utxo_commitment());
+        assert!(!chain.is_unspent(&stale_coinbase));
+    }
+
+    #[test]
+    fn orphans_connect_once_their_parent_arrives() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let mut source = chain.clone();
+        test_util::mine_blocks(&mut source, 3, &miner);
+
+        for height in [3, 2] {
+            assert!(matches!(
+                chain.add_block(source.blocks[height].clone()),
+                Err(BtcError::OrphanBlock)
+            ));
+        }
+        assert_eq!(chain.orphan_count(), 2);
+        assert_eq!(chain.block_height(), 1);
+        chain.add_block(source.blocks[1].clone()).unwrap();
+        assert_eq!(chain.orphan_count(), 0);
+        assert_eq!(chain.block_height(), 4);
+        assert_eq!(chain.utxo_commitment(), source.utxo_commitment());
+    }
+
+    #[test]
+    fn orphans_expire() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let mut source = chain.clone();
+        test_util::mine_blocks(&mut source, 3, &miner);
+        let now = Utc::now();
+        chain.set_clock(Arc::new(FixedClock(now)));
+        assert!(chain.add_block(source.blocks[2].clone()).is_err());
+        let later = now + chrono::Duration::seconds(crate::ORPHAN_EXPIRY + 1);
+        chain.set_clock(Arc::new(FixedClock(later)));
+        assert!(chain.add_block(source.blocks[3].clone()).is_err());
+        assert_eq!(chain.orphan_count(), 1);
+
+        // the expired orphan isn't connected with its parent
+        chain.add_block(source.blocks[1].clone()).unwrap();
+        assert_eq!(chain.block_height(), 2);
+        assert_eq!(chain.orphan_count(), 1);
+    }
+
+    #[test]
+    fn the_chain_follows_its_own_reward_schedule() {
+        let params = ChainParams {
+            initial_reward: Amount::from_sats(1000),
+            halving_interval: 2,
+            ..test_util::params()
+        };
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain =
+            Blockchain::new_with_genesis(params, miner.clone()).unwrap();
+        let reward = |block: &Block| block.transactions[0].outputs[0].value;
+        assert_eq!(reward(&chain.blocks[0]), Amount::from_sats(1000));
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        assert_eq!(reward(&chain.blocks[1]), Amount::from_sats(1000));
+
+        let mut greedy = test_util::next_block(&chain, vec![], &miner);
+        assert_eq!(reward(&greedy), Amount::from_sats(500));
+        greedy.transactions[0].outputs[0].value = Amount::from_sats(1000);
+        greedy.refresh_merkle_root();
+        assert!(greedy.header.mine(u64::MAX));
+        assert!(chain.add_block(greedy).is_err());
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        assert_eq!(
+            chain.audit_supply().unwrap().issued,
+            Amount::from_sats(2500)
+        );
+    }
+
+    #[test]
+    fn genesis_blocks_are_deterministic() {
+        let params = ChainParams {
+            min_target: U256::MAX >> 8,
+            ..test_util::params()
+        };
+        let key = PrivateKey::new_key().public_key();
+        let genesis = Block::genesis(&params, key.clone()).unwrap();
+        assert_eq!(
+            genesis.hash(),
+            Block::genesis(&params, key.clone()).unwrap().hash()
+        );
+        let other = PrivateKey::new_key().public_key();
+        assert_ne!(
+            genesis.hash(),
+            Block::genesis(&params, other).unwrap().hash()
+        );
+        assert_eq!(genesis.header.prev_block_hash, Hash::zero());
+        assert_eq!(genesis.header.timestamp, params.genesis_timestamp);
+        assert!(genesis.hash().matches_target(params.min_target));
+        assert_eq!(
+            genesis.transactions[0].outputs[0].value,
+            params.block_reward(0)
+        );
+
+        let chain =
+            Blockchain::new_with_genesis(params.clone(), key.clone()).unwrap();
+        assert_eq!(chain.blocks[0].hash(), genesis.hash());
+        let (outpoint, _) = test_util::coinbase_output(&genesis);
+        assert!(chain.is_unspent(&outpoint));
+
+        let mut checkpointed = params;
+        checkpointed.add_checkpoint(0, Hash::zero());
+        assert!(Blockchain::new_with_genesis(checkpointed, key).is_err());
+    }
+
+    #[test]
+    fn one_bad_timestamp_does_not_stall_the_chain() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        test_util::mine_blocks(&mut chain, 11, &miner);
+        // the median of the last 11 timestamps, 10 seconds apart
+        let timestamps: Vec<_> = chain
+            .iter_blocks()
+            .map(|block| block.header.timestamp)
+            .collect();
+        assert_eq!(chain.median_time_past(), timestamps[12 - 6]);
+
+        let at = |chain: &Blockchain, timestamp: DateTime<Utc>| {
+            let mut block = test_util::next_block(chain, vec![], &miner);
+            block.header.timestamp = timestamp;
+            assert!(block.header.mine(u64::MAX));
+            block
+        };
+        let tip = timestamps[11];
+        chain
+            .add_block(at(&chain, tip + chrono::Duration::hours(1)))
+            .unwrap();
+        // earlier than its parent, but after the median time past
+        chain
+            .add_block(at(&chain, tip + chrono::Duration::seconds(10)))
+            .unwrap();
+        assert_eq!(chain.block_height(), 14);
+    }
+
+    #[test]
+    fn blocks_too_far_in_the_future_are_rejected() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let now = chain.blocks[0].header.timestamp;
+        chain.set_clock(Arc::new(FixedClock(now)));
+        let drift =
+            chrono::Duration::seconds(chain.params().max_future_block_time);
+        let at = |chain: &Blockchain, timestamp: DateTime<Utc>| {
+            let mut block = test_util::next_block(chain, vec![], &miner);
+            block.header.timestamp = timestamp;
+            assert!(block.header.mine(u64::MAX));
+            block
+        };
+        let too_late = at(&chain, now + drift + chrono::Duration::seconds(1));
+        assert!(chain.add_block(too_late.clone()).is_err());
+        chain.add_block(at(&chain, now + drift)).unwrap();
+
+        // the same block is fine once the clock caught up
+        let mut later = chain.clone();
+        later.disconnect_tip().unwrap();
+        later.set_clock(Arc::new(FixedClock(
+            now + chrono::Duration::seconds(1),
+        )));
+        later.add_block(too_late).unwrap();
+    }
+
+    #[test]
+    fn block_templates_stay_within_the_block_limits() {
+        let key = PrivateKey::new_key();
+        let template_txids = |params: ChainParams| {
+            let mut chain =
+                Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+            test_util::mine_blocks(&mut chain, 2, &key.public_key());
+            let mut mempool = Mempool::new();
+            let mut by_fee = vec![];
+            for (block, fee) in chain.blocks.iter().zip([1000, 3000, 2000]) {
+                let coin = test_util::coinbase_output(block);
+                let change = coin.1.to_sats() - fee;
+                let transaction = spend(
+                    &key,
+                    &[coin],
+                    vec![output(change, &key.public_key())],
+                );
+                by_fee.push((fee, transaction.hash().to_string()));
+                mempool.add(transaction, &chain).unwrap();
+            }
+            by_fee.sort();
+            let template: serde_json::Value = serde_json::from_str(
+                &chain.get_block_template_json(&mempool, &key.public_key()),
+            )
+            .unwrap();
+            let included: Vec<String> = template["transactions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|transaction| transaction["txid"].as_str().unwrap().into())
+                .collect();
+            let expected: Vec<String> =
+                by_fee.into_iter().rev().map(|(_, txid)| txid).collect();
+            (included, expected)
+        };
+
+        let (included, expected) = template_txids(test_util::params());
+        assert_eq!(included, expected);
+        // the coinbase counts as one of the transactions
+        let (included, expected) = template_txids(ChainParams {
+            max_transactions_per_block: 3,
+            ..test_util::params()
+        });
+        assert_eq!(included, expected[..2]);
+        let (included, expected) = template_txids(ChainParams {
+            max_block_sigops: 1,
+            ..test_util::params()
+        });
+        assert_eq!(included, expected[..1]);
+    }
+
+    #[test]
+    fn every_output_gets_its_own_outpoint() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 3);
+        let txid = chain.blocks[1].transactions[1].hash();
+        let value = chain.blocks[1].transactions[1].outputs[0].value;
+        let outpoints: Vec<Outpoint> =
+            (0..3).map(|vout| Outpoint::new(txid, vout)).collect();
+        assert!(outpoints.iter().all(|outpoint| chain.is_unspent(outpoint)));
+        assert!(!chain.is_unspent(&Outpoint::new(txid, 3)));
+
+        let missing = spend(
+            &key,
+            &[(Outpoint::new(txid, 3), value)],
+            vec![output(1000, &key.public_key())],
+        );
+        assert!(chain.verify_transaction(&missing).is_err());
+        let last = spend(
+            &key,
+            &[(outpoints[2], value)],
+            vec![output(1000, &key.public_key())],
+        );
+        let block =
+            test_util::next_block(&chain, vec![last], &key.public_key());
+        chain.add_block(block).unwrap();
+        assert!(chain.is_unspent(&outpoints[0]));
+        assert!(chain.is_unspent(&outpoints[1]));
+        assert!(!chain.is_unspent(&outpoints[2]));
+    }
+
+    #[test]
+    fn disconnect_tip_puts_the_utxo_set_back() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 2);
+        let before = chain.utxo_commitment();
+        let txid = chain.blocks[1].transactions[1].hash();
+        let value = chain.blocks[1].transactions[1].outputs[0].value;
+        let spent = Outpoint::new(txid, 0);
+        let transaction = spend(
+            &key,
+            &[(spent, value)],
+            vec![output(1000, &key.public_key())],
+        );
+        let created = Outpoint::new(transaction.hash(), 0);
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+        let hash = block.hash();
+        chain.add_block(block).unwrap();
+        assert!(!chain.is_unspent(&spent));
+
+        assert_eq!(chain.disconnect_tip().unwrap().hash(), hash);
+        assert!(chain.is_unspent(&spent));
+        assert!(!chain.is_unspent(&created));
+        assert!(!chain.contains_transaction(&created.txid));
+        assert_eq!(chain.utxo_commitment(), before);
+
+        // pruned blocks have no undo data left
+        chain.prune(0);
+        assert!(matches!(
+            chain.disconnect_tip(),
+            Err(BtcError::MissingUndoData)
+        ));
+    }
+
+    #[test]
+    fn fork_choice_follows_work_not_height() {
+        let params = ChainParams {
+            min_target: U256::MAX >> 2,
+            difficulty_update_interval: 2,
+            ..test_util::params()
+        };
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain =
+            Blockchain::new_with_genesis(params, miner.clone()).unwrap();
+        let start = chain.blocks[0].header.timestamp;
+        let add_at = |chain: &mut Blockchain, seconds: i64| {
+            let mut block = test_util::next_block(chain, vec![], &miner);
+            block.header.timestamp = start + chrono::Duration::seconds(seconds);
+            block.header.nonce = 0;
+            assert!(block.header.mine(u64::MAX));
+            chain.add_block(block).unwrap();
+        };
+        let mut fast = chain.clone();
+        for seconds in [10, 20, 30] {
+            add_at(&mut chain, seconds);
+        }
+        // a quick second block makes the third one much harder
+        add_at(&mut fast, 2);
+        add_at(&mut fast, 4);
+        assert!(fast.block_height() < chain.block_height());
+        assert!(fast.total_work() > chain.total_work());
+        let work = fast
+            .iter_blocks()
+            .fold(U256::zero(), |work, block| work + block.header.work());
+        assert_eq!(fast.total_work(), work);
+
+        for block in &fast.blocks[1..] {
+            chain.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(chain.block_height(), fast.block_height());
+        assert_eq!(
+            chain.blocks.last().unwrap().hash(),
+            fast.blocks.last().unwrap().hash()
+        );
+        assert_eq!(chain.total_work(), fast.total_work());
+    }
+
+    #[test]
+    fn chain_tips_flag_branches_that_failed_to_connect() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        let mut fork = chain.clone();
+        test_util::mine_blocks(&mut chain, 1, &miner);
+        let other = PrivateKey::new_key().public_key();
+        test_util::mine_blocks(&mut fork, 1, &other);
+        let mut greedy = test_util::next_block(&fork, vec![], &other);
+        greedy.transactions[0].outputs[0].value = greedy.transactions[0]
+            .outputs[0]
+            .value
+            .checked_add(Amount::from_sats(1))
+            .unwrap();
+        greedy.refresh_merkle_root();
+        assert!(greedy.header.mine(u64::MAX));
+
+        chain.add_block(fork.blocks[1].clone()).unwrap();
+        assert!(chain.add_block(greedy.clone()).is_err());
+        let tips = chain.chain_tips();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].status, ChainTipStatus::Active);
+        assert_eq!(tips[0].hash, chain.blocks[1].hash());
+        assert_eq!(tips[1].hash, greedy.hash());
+        assert_eq!(tips[1].height, 2);
+        assert_eq!(tips[1].status, ChainTipStatus::Invalid);
+    }
+
+    #[test]
+    fn branches_contradicting_a_checkpoint_are_refused() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut honest = test_util::chain(&miner);
+        let mut attacker = honest.clone();
+        test_util::mine_blocks(&mut honest, 1, &miner);
+        test_util::mine_blocks(
+            &mut attacker,
+            3,
+            &PrivateKey::new_key().public_key(),
+        );
+
+        let mut params = test_util::params();
+        params.add_checkpoint(1, honest.blocks[1].hash());
+        // the genesis block is deterministic, so both chains share it
+        let mut chain = Blockchain::new_with_genesis(params, miner).unwrap();
+        assert!(chain.add_block(attacker.blocks[1].clone()).is_err());
+        chain.add_block(honest.blocks[1].clone()).unwrap();
+        // not even with more work
+        for block in &attacker.blocks[1..] {
+            assert!(chain.add_block(block.clone()).is_err());
+        }
+        assert_eq!(chain.block_height(), 2);
+        assert_eq!(chain.blocks[1].hash(), honest.blocks[1].hash());
+    }
+
+    #[test]
+    fn signatures_are_only_skipped_up_to_the_assumed_valid_block() {
+        let key = PrivateKey::new_key();
+        let thief = PrivateKey::new_key();
+        let plain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&plain.blocks[0]);
+        let stolen =
+            spend(&thief, &[funds], vec![output(1000, &thief.public_key())]);
+        let block =
+            test_util::next_block(&plain, vec![stolen], &key.public_key());
+        assert!(plain.clone().add_block(block.clone()).is_err());
+
+        let params = ChainParams {
+            assume_valid: Some((1, block.hash())),
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        chain.add_block(block).unwrap();
+        // past the assumed valid block signatures are checked again
+        let funds = test_util::coinbase_output(&chain.blocks[1]);
+        let stolen =
+            spend(&thief, &[funds], vec![output(1000, &thief.public_key())]);
+        let block =
+            test_util::next_block(&chain, vec![stolen], &key.public_key());
+        assert!(chain.add_block(block).is_err());
+
+        // everything but the signatures is still checked below it
+        let params = ChainParams {
+            assume_valid: Some((2, Hash::zero())),
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let sats = funds.1.to_sats() + 1;
+        let overspending =
+            spend(&thief, &[funds], vec![output(sats, &thief.public_key())]);
+        let block = test_util::next_block(
+            &chain,
+            vec![overspending],
+            &key.public_key(),
+        );
+        assert!(chain.add_block(block).is_err());
+    }
+
+    #[test]
+    fn signatures_are_only_skipped_for_ancestors_of_the_assumed_valid_block() {
+        let key = PrivateKey::new_key();
+        let thief = PrivateKey::new_key();
+        let plain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&plain.blocks[0]);
+        let stolen =
+            spend(&thief, &[funds], vec![output(1000, &thief.public_key())]);
+        let forged =
+            test_util::next_block(&plain, vec![stolen], &key.public_key());
+        // a chain trusting the forged block, to build on it
+        let params = ChainParams {
+            assume_valid: Some((1, forged.hash())),
+            ..test_util::params()
+        };
+        let mut forger =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        forger.add_block(forged.clone()).unwrap();
+        test_util::mine_blocks(&mut forger, 1, &key.public_key());
+
+        // once its header is imported, the assumed valid block vouches for
+        // its ancestors
+        let params = ChainParams {
+            assume_valid: Some((2, forger.blocks[2].hash())),
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        let headers = forger.blocks[1..]
+            .iter()
+            .map(|block| block.header.clone())
+            .collect();
+        chain.import_headers(headers).unwrap();
+        for height in 1..=2 {
+            let body = forger.blocks[height].transactions.clone();
+            chain.fill_body(height as u64, body).unwrap();
+        }
+
+        // but a block below the assumed height may be on any branch, so
+        // it isn't trusted, whether it extends the tip or forks from it
+        let params = ChainParams {
+            assume_valid: Some((3, Hash::zero())),
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        assert!(chain.add_block(forged.clone()).is_err());
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let honest = chain.blocks[1].hash();
+        chain.add_block(forged).unwrap();
+        assert!(chain.add_block(forger.blocks[2].clone()).is_err());
+        assert_eq!(chain.block_height(), 2);
+        assert_eq!(chain.blocks[1].hash(), honest);
+    }
+
+    #[test]
+    fn deployments_activate_a_window_after_locking_in() {
+        let miner = PrivateKey::new_key().public_key();
+        let start = test_util::params().genesis_timestamp;
+        let deployment = |bit: u8, timeout: DateTime<Utc>| Deployment {
+            name: format!("bit {bit}"),
+            bit,
+            start_time: start,
+            timeout,
+        };
+        let signalled = deployment(1, start + chrono::Duration::days(1));
+        let ignored = deployment(2, start + chrono::Duration::seconds(30));
+        let params = ChainParams {
+            miner_confirmation_window: 4,
+            rule_change_activation_threshold: 3,
+            deployments: vec![signalled.clone(), ignored.clone()],
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, miner.clone()).unwrap();
+        let mine = |chain: &mut Blockchain, count: usize| {
+            for _ in 0..count {
+                let mut block = test_util::next_block(chain, vec![], &miner);
+                block.header.version = crate::VERSIONBITS_TOP_BITS | 1 << 1;
+                assert!(block.header.mine(u64::MAX));
+                chain.add_block(block).unwrap();
+            }
+        };
+        let states = |chain: &Blockchain| {
+            let height = chain.block_height();
+            (
+                chain.deployment_state(&signalled, height),
+                chain.deployment_state(&ignored, height),
+            )
+        };
+
+        // the state is the next block's, which starts a window once the
+        // chain has 4 blocks
+        mine(&mut chain, 2);
+        assert_eq!(
+            states(&chain),
+            (DeploymentState::Defined, DeploymentState::Defined)
+        );
+        assert_eq!(chain.next_block_version(), crate::VERSIONBITS_TOP_BITS);
+        mine(&mut chain, 1);
+        assert_eq!(
+            states(&chain),
+            (DeploymentState::Started, DeploymentState::Started)
+        );
+        assert_eq!(
+            chain.next_block_version(),
+            crate::VERSIONBITS_TOP_BITS | 1 << 1 | 1 << 2
+        );
+        mine(&mut chain, 4);
+        assert_eq!(
+            states(&chain),
+            (DeploymentState::LockedIn, DeploymentState::Failed)
+        );
+        assert_eq!(
+            chain.next_block_version(),
+            crate::VERSIONBITS_TOP_BITS | 1 << 1
+        );
+        mine(&mut chain, 4);
+        assert_eq!(
+            states(&chain),
+            (DeploymentState::Active, DeploymentState::Failed)
+        );
+        assert_eq!(chain.next_block_version(), crate::VERSIONBITS_TOP_BITS);
+    }
+
+    #[test]
+    fn transactions_already_in_the_chain_are_rejected() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 2);
+        let confirmed = chain.blocks[1].transactions[1].clone();
+        assert!(chain.contains_transaction(&confirmed.hash()));
+        assert!(matches!(
+            chain.verify_transaction(&confirmed),
+            Err(BtcError::InvalidTransaction)
+        ));
+        let block =
+            test_util::next_block(&chain, vec![confirmed], &key.public_key());
+        assert!(chain.add_block(block).is_err());
+
+        // gone once its block is disconnected
+        let txid = chain.blocks[1].transactions[1].hash();
+        chain.disconnect_tip().unwrap();
+        assert!(!chain.contains_transaction(&txid));
+    }
+
+    #[test]
+    fn outputs_cannot_add_up_to_more_than_the_money_supply() {
+        let pubkey = PrivateKey::new_key().public_key();
+        let max = crate::MAX_MONEY.to_sats();
+        let paying = |values: &[u64]| {
+            Transaction::new(
+                vec![],
+                values.iter().map(|value| output(*value, &pubkey)).collect(),
+            )
+        };
+        paying(&[max]).verify_money_range(crate::MAX_MONEY).unwrap();
+        paying(&[max - 1, 1])
+            .verify_money_range(crate::MAX_MONEY)
+            .unwrap();
+        for values in [&[max + 1][..], &[max, 1], &[u64::MAX, u64::MAX]] {
+            assert!(matches!(
+                paying(values).verify_money_range(crate::MAX_MONEY),
+                Err(BtcError::InvalidTransaction)
+            ));
+        }
+
+        // not even the genesis coinbase may go past it
+        let params = ChainParams {
+            max_money: Amount::from_sats(1000),
+            ..test_util::params()
+        };
+        assert!(Blockchain::new_with_genesis(params, pubkey).is_err());
+    }
+
+    #[test]
+    fn supply_audits_catch_coins_out_of_thin_air() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 2);
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let audit = chain.audit_supply().unwrap();
+        let reward = chain.params().block_reward(0).to_sats();
+        assert_eq!(audit.issued, Amount::from_sats(3 * reward));
+        assert_eq!(audit.unclaimed, Amount::ZERO);
+        assert_eq!(audit.utxo_total, audit.issued);
+        assert!(audit.is_consistent());
+
+        let mut inflated = chain.clone();
+        test_util::mine_blocks(&mut inflated, 1, &key.public_key());
+        chain.utxos = inflated.utxos;
+        assert!(!chain.audit_supply().unwrap().is_consistent());
+
+        chain.prune(0);
+        assert!(matches!(
+            chain.audit_supply(),
+            Err(BtcError::MissingUndoData)
+        ));
+    }
+
+    #[test]
+    fn supply_audits_report_overflowing_outputs() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 2);
+        // as if a broken consensus change had let block 1 create coins far
+        // past the money supply
+        for transaction in &mut chain.blocks[1].transactions {
+            for output in &mut transaction.outputs {
+                output.value = Amount::from_sats(u64::MAX);
+            }
+        }
+        chain.rebuild_utxos();
+        let audit = chain.audit_supply().unwrap();
+        assert_eq!(audit.utxo_total, Amount::from_sats(u64::MAX));
+        assert!(!audit.is_consistent());
+    }
+
+    #[test]
+    fn validation_reports_list_every_broken_rule() {
+        let key = PrivateKey::new_key();
+        let chain = chain_with_utxos(&key, 3);
+        let txid = chain.blocks[1].transactions[1].hash();
+        let value = chain.blocks[1].transactions[1].outputs[0].value;
+        let coin = |vout: u32| (Outpoint::new(txid, vout), value);
+        let pay = || vec![output(1000, &key.public_key())];
+        let miner = key.public_key();
+        let height = chain.block_height();
+
+        let valid = test_util::next_block(
+            &chain,
+            vec![spend(&key, &[coin(0)], pay())],
+            &miner,
+        );
+        assert!(valid
+            .validate(height, &chain.utxos, chain.params())
+            .is_valid());
+
+        let thief = PrivateKey::new_key();
+        let missing = (Outpoint::new(Hash::zero(), 0), value);
+        let block = test_util::next_block(
+            &chain,
+            vec![
+                spend(&thief, &[coin(0)], pay()),
+                spend(&key, &[missing], pay()),
+                spend(&key, &[coin(1), coin(1)], pay()),
+            ],
+            &miner,
+        );
+        let report = block.validate(height, &chain.utxos, chain.params());
+        let violations: Vec<_> = report
+            .violations
+            .iter()
+            .map(|violation| {
+                (violation.rule, violation.transaction, violation.input)
+            })
+            .collect();
+        // the coinbase claims the fees of the invalid transactions too
+        assert_eq!(
+            violations,
+            vec![
+                ("bad-signature", Some(1), Some(0)),
+                ("missing-input", Some(2), Some(0)),
+                ("duplicate-input", Some(3), Some(1)),
+                ("coinbase-value", Some(0), None),
+            ]
+        );
+    }
+
+    // Listener recording the transactions added, with their height.
+    #[derive(Debug, Default)]
+    struct AddedTransactions(Mutex<Vec<(u64, Hash)>>);
+
+    impl ChainListener for AddedTransactions {
+        fn on_transaction_added(&self, transaction: &Transaction, height: u64) {
+            self.0.lock().unwrap().push((height, transaction.hash()));
+        }
+    }
+
+    #[test]
+    fn listeners_follow_transactions_into_the_chain() {
+        let key = PrivateKey::new_key();
+        let mut chain = test_util::chain(&key.public_key());
+        let added = Arc::new(AddedTransactions::default());
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        chain.add_listener(added.clone());
+        chain.add_listener(mempool.clone());
+
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction =
+            spend(&key, &[funds], vec![output(1000, &key.public_key())]);
+        mempool
+            .lock()
+            .unwrap()
+            .add(transaction.clone(), &chain)
+            .unwrap();
+        let block = test_util::next_block(
+            &chain,
+            vec![transaction.clone()],
+            &key.public_key(),
+        );
+        let coinbase = block.transactions[0].hash();
+        chain.add_block(block).unwrap();
+        assert_eq!(
+            *added.0.lock().unwrap(),
+            vec![(1, coinbase), (1, transaction.hash())]
+        );
+        assert!(mempool.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn check_needs_no_chain_but_contextual_check_does() {
+        let miner = PrivateKey::new_key().public_key();
+        let chain = test_util::chain(&miner);
+        let mut source = chain.clone();
+        test_util::mine_blocks(&mut source, 1, &miner);
+        // the parent is unknown to `chain`, which only the context catches
+        let orphan = test_util::next_block(&source, vec![], &miner);
+        orphan.check().unwrap();
+        assert!(chain.contextual_check(&orphan).is_err());
+        let block = test_util::next_block(&chain, vec![], &miner);
+        chain.contextual_check(&block).unwrap();
+
+        let mut empty = block.clone();
+        empty.transactions.clear();
+        assert!(matches!(empty.check(), Err(BtcError::InvalidBlock)));
+        let mut two_coinbases = block.clone();
+        two_coinbases
+            .transactions
+            .push(Transaction::coinbase(1, vec![]));
+        two_coinbases.refresh_merkle_root();
+        assert!(matches!(
+            two_coinbases.check(),
+            Err(BtcError::InvalidTransaction)
+        ));
+        let mut tampered = block.clone();
+        tampered.transactions[0].outputs[0].value = Amount::ZERO;
+        assert!(matches!(tampered.check(), Err(BtcError::InvalidMerkleRoot)));
+        let mut unmined = block;
+        unmined.header.target = U256::zero();
+        assert!(matches!(unmined.check(), Err(BtcError::InvalidBlock)));
+    }
+
+    #[test]
+    fn blocks_with_too_many_signatures_are_rejected() {
+        let key = PrivateKey::new_key();
+        let params = ChainParams {
+            max_block_sigops: 1,
+            ..test_util::params()
+        };
+        let mut chain =
+            Blockchain::new_with_genesis(params, key.public_key()).unwrap();
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let funds: Vec<_> = chain
+            .iter_blocks()
+            .map(test_util::coinbase_output)
+            .collect();
+        let both = spend(&key, &funds, vec![output(1000, &key.public_key())]);
+        assert_eq!(both.sigop_count(), 2);
+        let block =
+            test_util::next_block(&chain, vec![both], &key.public_key());
+        assert_eq!(block.sigop_count(), 2);
+        assert!(matches!(
+            block.verify_sigops(chain.params()),
+            Err(BtcError::TooManySigops)
+        ));
+        assert!(chain.add_block(block).is_err());
+
+        let one =
+            spend(&key, &funds[..1], vec![output(1000, &key.public_key())]);
+        let block = test_util::next_block(&chain, vec![one], &key.public_key());
+        block.verify_sigops(chain.params()).unwrap();
+        chain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn iterators_walk_the_chain_in_order() {
+        let key = PrivateKey::new_key();
+        let mut chain = chain_with_utxos(&key, 2);
+        test_util::mine_blocks(&mut chain, 1, &key.public_key());
+        let heights: Vec<u64> = chain
+            .iter_transactions()
+            .map(|(height, _)| height)
+            .collect();
+        assert_eq!(heights, vec![0, 1, 1, 2]);
+        let tip = chain.iter_blocks().next_back().unwrap();
+        assert_eq!(tip.hash(), chain.blocks[2].hash());
+
+        let split = chain.blocks[1].transactions[1].hash();
+        let (height, found) = chain.find_transaction(&split).unwrap();
+        assert_eq!((height, found.hash()), (1, split));
+        assert!(chain.find_transaction(&Hash::zero()).is_none());
+
+        // the two outputs of the split and the last coinbase, the second
+        // block's going to a random miner
+        let mut mine: Vec<Outpoint> = chain
+            .iter_utxos_for_pubkey(&key.public_key())
+            .map(|(outpoint, _)| *outpoint)
+            .collect();
+        mine.sort();
+        let mut expected = vec![
+            Outpoint::new(split, 0),
+            Outpoint::new(split, 1),
+            test_util::coinbase_output(&chain.blocks[2]).0,
+        ];
+        expected.sort();
+        assert_eq!(mine, expected);
+
+        chain.prune(1);
+        assert!(chain.find_transaction(&split).is_none());
+    }
+
+    #[test]
+    fn locators_find_where_a_peer_forked() {
+        let miner = PrivateKey::new_key().public_key();
+        let mut chain = test_util::chain(&miner);
+        test_util::mine_blocks(&mut chain, 14, &miner);
+        let mut peer = chain.clone();
+        test_util::mine_blocks(&mut chain, 15, &miner);
+        test_util::mine_blocks(
+            &mut peer,
+            5,
+            &PrivateKey::new_key().public_key(),
+        );
+
+        let at = |heights: &[usize]| -> Vec<Hash> {
+            heights.iter().map(|h| chain.blocks[*h].hash()).collect()
+        };
+        let mut expected: Vec<usize> = (20..30).rev().collect();
+        expected.extend([18, 14, 6, 0]);
+        assert_eq!(chain.block_locator(), at(&expected));
+
+        assert_eq!(chain.find_fork_point(&peer.block_locator()), Some(14));
+        assert_eq!(peer.find_fork_point(&chain.block_locator()), Some(14));
+        assert_eq!(chain.find_fork_point(&chain.block_locator()), Some(29));
+        let stranger = test_util::chain(&PrivateKey::new_key().public_key());
+        assert_eq!(chain.find_fork_point(&stranger.block_locator()), None);
+        assert!(Blockchain::new().block_locator().is_empty());
     }
 }
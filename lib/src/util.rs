@@ -1,7 +1,57 @@
 use crate::sha256::Hash;
-use crate::types::Transaction;
+use crate::types::{BlockHeader, Outpoint, Transaction};
+use crate::utxo::UtxoSet;
+use crate::U256;
 use serde::{Deserialize, Serialize};
 
+// Serialize anything that can be serde Serialized via ciborium, the
+// encoding used for hashing and sizes.
+pub(crate) fn serialize<T: Serialize>(data: &T) -> Vec<u8> {
+    //create the buffer for storing the serialized value
+    let mut serialized: Vec<u8> = vec![];
+    // writing into a Vec can't fail, only a broken Serialize impl can
+    if let Err(e) = ciborium::into_writer(data, &mut serialized) {
+        panic!(
+            "Failed to serialize data: {:?}. \
+        This should not happen.",
+            e
+        )
+    }
+    serialized
+}
+
+/// Closest f64 to a U256, for ratios where a rounded result is enough.
+pub fn u256_to_f64(value: U256) -> f64 {
+    // words are stored least significant first
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, word| acc * 2f64.powi(64) + *word as f64)
+}
+
+/// Whether the hash of `header` meets `share_target`, whatever its own
+/// target. Pools use an easier share target than the block's to see how
+/// much work each miner does, even if its headers don't make valid blocks.
+pub fn check_share(header: &BlockHeader, share_target: U256) -> bool {
+    header.hash().matches_target(share_target)
+}
+
+// Merkle tree leaves and inner nodes are hashed with different tags, so an
+// inner node can never be passed off as a leaf or the other way around.
+const MERKLE_LEAF_TAG: &str = "btc/merkle-leaf";
+const MERKLE_NODE_TAG: &str = "btc/merkle-node";
+
+/// Hash of a merkle tree leaf committing to `data`.
+pub fn merkle_leaf<T: Serialize>(data: &T) -> Hash {
+    Hash::tagged(MERKLE_LEAF_TAG, data)
+}
+
+// Hash of the merkle tree node with the given children.
+fn merkle_node(left: Hash, right: Hash) -> Hash {
+    Hash::tagged(MERKLE_NODE_TAG, &[left, right])
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MerkleRoot(Hash);
 
@@ -18,30 +68,650 @@ impl MerkleRoot {
     ///
     /// # How it works
     ///
-    /// 1. The function starts by hashing each individual transaction and placing these hashes in the `layer` vector.
+    /// 1. The function starts by hashing each individual transaction as a leaf (see `merkle_leaf`) and placing these
+    ///    hashes in the `layer` vector.
     /// 2. It then iteratively combines pairs of hashes from the current layer into a new layer, by hashing the concatenation
-    ///    of two hashes at a time (or duplicating the last hash if the number of hashes is odd).
+    ///    of two hashes at a time as an inner node (or duplicating the last hash if the number of hashes is odd).
     /// 3. This process continues until only a single hash remains, which represents the root of the Merkle tree.
     ///
-    /// The Merkle root is then returned as the root hash.
+    /// The Merkle root is then returned as the root hash. Note that with a single transaction the root is its leaf
+    /// hash, which differs from `Transaction::hash` because of the leaf tag.
     pub fn calculate(trasactions: &[Transaction]) -> MerkleRoot {
         let mut layer: Vec<Hash> = vec![];
         // Hash each transaction and add it to the first layer of the tree.
         for trasaction in trasactions {
-            layer.push(Hash::hash(trasaction));
+            layer.push(merkle_leaf(trasaction));
         }
         // Combine pairs of hashes from the current layer into a new layer.
         while layer.len() > 1 {
-            let mut new_layer = vec![];
-            //Each pair of hashes is concatenated and hashed to create a new hash.
-            for pair in layer.chunks(2) {
-                let left = pair[0];
-                //if there is no right, use the left hash again
-                let right = pair.get(1).unwrap_or(&pair[0]);
-                new_layer.push(Hash::hash(&[left, *right]));
-            }
-            layer = new_layer
+            layer = next_merkle_layer(&layer);
         }
         MerkleRoot(layer[0])
     }
+
+    /// Merkle path proving that `transactions[index]` is committed to by
+    /// the root of `transactions`, or None if `index` is out of range.
+    pub fn proof(
+        transactions: &[Transaction],
+        index: usize,
+    ) -> Option<MerkleProof> {
+        let leaves: Vec<Hash> = transactions.iter().map(merkle_leaf).collect();
+        merkle_proof(&leaves, index).map(|siblings| MerkleProof { siblings })
+    }
+}
+
+/// Proof that a transaction is committed to by a merkle root, without the
+/// other transactions. Built by `MerkleRoot::proof`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Sibling hashes from the leaf up to the root, each with whether it is
+    /// the left one. A node without a sibling (the last one of an odd
+    /// layer) is paired with itself, like in `MerkleRoot::calculate`.
+    pub siblings: Vec<(Hash, bool)>,
+}
+
+impl MerkleProof {
+    /// Check that `transaction` is part of the tree with root `root`.
+    pub fn verify(&self, transaction: &Transaction, root: &MerkleRoot) -> bool {
+        verify_merkle_proof(merkle_leaf(transaction), &self.siblings, root.0)
+    }
+}
+
+/// Checks a stream of transactions against a merkle root without keeping
+/// them around: only one pending hash per tree level is stored, so memory
+/// grows with the logarithm of the number of transactions.
+///
+/// Transactions must be pushed in block order; `finish` then compares the
+/// computed root with the expected one.
+pub struct MerkleVerifier {
+    expected: MerkleRoot,
+    // pending[level] holds a left node waiting for its right sibling. As
+    // pairs are only combined once complete, this works like a binary
+    // counter of the pushed transactions.
+    pending: Vec<Option<Hash>>,
+    count: usize,
+}
+
+impl MerkleVerifier {
+    pub fn new(expected: MerkleRoot) -> Self {
+        MerkleVerifier {
+            expected,
+            pending: vec![],
+            count: 0,
+        }
+    }
+
+    /// Add the next transaction of the block.
+    pub fn push(&mut self, transaction: &Transaction) {
+        let mut node = merkle_leaf(transaction);
+        let mut level = 0;
+        // carry the node up while it completes a pair
+        while let Some(left) =
+            self.pending.get_mut(level).and_then(Option::take)
+        {
+            node = merkle_node(left, node);
+            level += 1;
+        }
+        if level == self.pending.len() {
+            self.pending.push(None);
+        }
+        self.pending[level] = Some(node);
+        self.count += 1;
+    }
+
+    /// Root of the transactions pushed so far, `None` if there are none.
+    pub fn root(&self) -> Option<MerkleRoot> {
+        // close the incomplete pairs from the bottom, duplicating the last
+        // node of every layer with an odd length like `calculate` does
+        let mut layer_len = self.count;
+        let mut carry: Option<Hash> = None;
+        for pending in &self.pending {
+            if layer_len == 1 {
+                return pending.or(carry).map(MerkleRoot);
+            }
+            carry = match (*pending, carry) {
+                (Some(left), Some(right)) => Some(merkle_node(left, right)),
+                (Some(last), None) | (None, Some(last)) => {
+                    Some(merkle_node(last, last))
+                }
+                (None, None) => None,
+            };
+            layer_len = layer_len.div_ceil(2);
+        }
+        carry.map(MerkleRoot)
+    }
+
+    /// Whether the pushed transactions match the expected merkle root.
+    pub fn finish(self) -> bool {
+        self.root() == Some(self.expected)
+    }
+}
+
+/// The matched transactions of a block with just the hashes needed to
+/// rebuild its merkle root, like Bitcoin's merkleblock, so a light client
+/// can check them without downloading the whole block.
+///
+/// The tree is walked depth first. Each node visited has a flag telling
+/// whether a matched transaction is below it: its children are only
+/// visited when it is set, otherwise the node's hash is included instead.
+/// Leaves always carry their hash, so the matched ones are included too.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    /// Number of transactions in the block.
+    pub transaction_count: u32,
+    /// Flags in visiting order, packed eight per byte, least significant
+    /// bit first.
+    pub flags: Vec<u8>,
+    /// Hashes in visiting order.
+    pub hashes: Vec<Hash>,
+}
+
+impl PartialMerkleTree {
+    /// Build the tree of `transactions` keeping those whose txid is in
+    /// `matched`.
+    pub fn new(transactions: &[Transaction], matched: &[Hash]) -> Self {
+        let leaves: Vec<Hash> = transactions.iter().map(merkle_leaf).collect();
+        let matches: Vec<bool> = transactions
+            .iter()
+            .map(|transaction| matched.contains(&transaction.hash()))
+            .collect();
+        let mut tree = PartialMerkleTree {
+            transaction_count: transactions.len() as u32,
+            flags: vec![],
+            hashes: vec![],
+        };
+        if !leaves.is_empty() {
+            let mut flag_count = 0;
+            let height = tree.tree_height();
+            tree.build(height, 0, &leaves, &matches, &mut flag_count);
+        }
+        tree
+    }
+
+    /// Rebuild the merkle root, with the position and leaf hash (see
+    /// `merkle_leaf`) of every matched transaction. None if the tree is
+    /// malformed: empty, too short, with unused flags or hashes, or with
+    /// two identical siblings, which could hide duplicated transactions.
+    pub fn extract_matches(&self) -> Option<(MerkleRoot, Vec<(usize, Hash)>)> {
+        let count = self.transaction_count as usize;
+        // every hash needs a flag, and there can't be more than one per
+        // transaction
+        if count == 0
+            || self.hashes.len() > count
+            || self.flags.len() * 8 < self.hashes.len()
+        {
+            return None;
+        }
+        let mut flag_count = 0;
+        let mut hash_count = 0;
+        let mut matches = vec![];
+        let root = self.extract(
+            self.tree_height(),
+            0,
+            &mut flag_count,
+            &mut hash_count,
+            &mut matches,
+        )?;
+        // everything must have been used, the padding of the last byte aside
+        if flag_count.div_ceil(8) != self.flags.len()
+            || hash_count != self.hashes.len()
+        {
+            return None;
+        }
+        Some((MerkleRoot(root), matches))
+    }
+
+    // Number of nodes at `height`, 0 being the leaves.
+    fn width(&self, height: u32) -> usize {
+        (self.transaction_count as usize).div_ceil(1 << height)
+    }
+
+    // Height of the root.
+    fn tree_height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        height
+    }
+
+    fn flag(&self, index: usize) -> Option<bool> {
+        let byte = self.flags.get(index / 8)?;
+        Some(byte & (1 << (index % 8)) != 0)
+    }
+
+    fn push_flag(&mut self, flag: bool, flag_count: &mut usize) {
+        if flag_count.is_multiple_of(8) {
+            self.flags.push(0);
+        }
+        if flag {
+            *self.flags.last_mut().unwrap() |= 1 << (*flag_count % 8);
+        }
+        *flag_count += 1;
+    }
+
+    // Hash of the node at `height` and `position`, from all the leaves.
+    fn node_hash(&self, height: u32, position: usize, leaves: &[Hash]) -> Hash {
+        if height == 0 {
+            return leaves[position];
+        }
+        let left = self.node_hash(height - 1, position * 2, leaves);
+        // the last node of an odd layer is paired with itself
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            self.node_hash(height - 1, position * 2 + 1, leaves)
+        } else {
+            left
+        };
+        merkle_node(left, right)
+    }
+
+    fn build(
+        &mut self,
+        height: u32,
+        position: usize,
+        leaves: &[Hash],
+        matches: &[bool],
+        flag_count: &mut usize,
+    ) {
+        let first = position << height;
+        let last = ((position + 1) << height).min(leaves.len());
+        let has_match = matches[first..last].iter().any(|matched| *matched);
+        self.push_flag(has_match, flag_count);
+        if height == 0 || !has_match {
+            let hash = self.node_hash(height, position, leaves);
+            self.hashes.push(hash);
+            return;
+        }
+        self.build(height - 1, position * 2, leaves, matches, flag_count);
+        if position * 2 + 1 < self.width(height - 1) {
+            self.build(
+                height - 1,
+                position * 2 + 1,
+                leaves,
+                matches,
+                flag_count,
+            );
+        }
+    }
+
+    fn extract(
+        &self,
+        height: u32,
+        position: usize,
+        flag_count: &mut usize,
+        hash_count: &mut usize,
+        matches: &mut Vec<(usize, Hash)>,
+    ) -> Option<Hash> {
+        let has_match = self.flag(*flag_count)?;
+        *flag_count += 1;
+        if height == 0 || !has_match {
+            let hash = *self.hashes.get(*hash_count)?;
+            *hash_count += 1;
+            if height == 0 && has_match {
+                matches.push((position, hash));
+            }
+            return Some(hash);
+        }
+        let left = self.extract(
+            height - 1,
+            position * 2,
+            flag_count,
+            hash_count,
+            matches,
+        )?;
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            let right = self.extract(
+                height - 1,
+                position * 2 + 1,
+                flag_count,
+                hash_count,
+                matches,
+            )?;
+            // a real right sibling equal to the left one would let the
+            // same root commit to a different list of transactions
+            if right == left {
+                return None;
+            }
+            right
+        } else {
+            left
+        };
+        Some(merkle_node(left, right))
+    }
+}
+
+// Combine each pair of hashes of a merkle tree layer into the layer above it.
+fn next_merkle_layer(layer: &[Hash]) -> Vec<Hash> {
+    let mut new_layer = vec![];
+    //Each pair of hashes is concatenated and hashed to create a new hash.
+    for pair in layer.chunks(2) {
+        let left = pair[0];
+        //if there is no right, use the left hash again
+        let right = pair.get(1).unwrap_or(&pair[0]);
+        new_layer.push(merkle_node(left, *right));
+    }
+    new_layer
+}
+
+/// Calculates the root of a merkle tree built over `leaves`, using the same
+/// pairing rules as `MerkleRoot::calculate`. An empty tree has a zero root.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::zero();
+    }
+    let mut layer = leaves.to_vec();
+    while layer.len() > 1 {
+        layer = next_merkle_layer(&layer);
+    }
+    layer[0]
+}
+
+/// Builds the merkle path proving that `leaves[index]` is part of the tree.
+///
+/// Each step of the path is the sibling hash together with a flag telling
+/// whether the sibling sits on the left. When a layer has an odd length the
+/// last hash is its own sibling, mirroring how the root is calculated.
+/// Returns `None` if `index` is out of bounds.
+pub fn merkle_proof(
+    leaves: &[Hash],
+    index: usize,
+) -> Option<Vec<(Hash, bool)>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut proof = vec![];
+    let mut layer = leaves.to_vec();
+    let mut index = index;
+    while layer.len() > 1 {
+        let step = if index.is_multiple_of(2) {
+            // we are the left node, the sibling is on the right (or is
+            // ourselves if the layer has an odd length)
+            (*layer.get(index + 1).unwrap_or(&layer[index]), false)
+        } else {
+            (layer[index - 1], true)
+        };
+        proof.push(step);
+        layer = next_merkle_layer(&layer);
+        index /= 2;
+    }
+    Some(proof)
+}
+
+/// Checks a merkle path produced by `merkle_proof` by recomputing the root
+/// from `leaf` and comparing it with `root`.
+pub fn verify_merkle_proof(
+    leaf: Hash,
+    proof: &[(Hash, bool)],
+    root: Hash,
+) -> bool {
+    let mut current = leaf;
+    for (sibling, sibling_is_left) in proof {
+        current = if *sibling_is_left {
+            merkle_node(*sibling, current)
+        } else {
+            merkle_node(current, *sibling)
+        };
+    }
+    current == root
+}
+
+/// Position of the leaf a merkle path produced by `merkle_proof` starts
+/// from, read from which side each sibling is on. `None` if the path is too
+/// long for the position to fit.
+pub fn merkle_proof_index(proof: &[(Hash, bool)]) -> Option<usize> {
+    if proof.len() >= usize::BITS as usize {
+        return None;
+    }
+    Some(
+        proof
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, sibling_is_left))| *sibling_is_left)
+            .map(|(depth, _)| 1 << depth)
+            .sum(),
+    )
+}
+
+/// Whether a merkle path produced by `merkle_proof` starts from the last
+/// leaf of the tree: at every layer the node is either the right one or
+/// paired with itself. Only meaningful once the path is verified.
+pub fn merkle_proof_is_last(leaf: Hash, proof: &[(Hash, bool)]) -> bool {
+    let mut current = leaf;
+    for (sibling, sibling_is_left) in proof {
+        if !*sibling_is_left && *sibling != current {
+            return false;
+        }
+        current = if *sibling_is_left {
+            merkle_node(*sibling, current)
+        } else {
+            merkle_node(current, *sibling)
+        };
+    }
+    true
+}
+
+/// Differences between two UTXO sets, as returned by `utxo_diff`. Every
+/// list is sorted by outpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UtxoDiff {
+    /// Outpoints only present in the first set.
+    pub only_in_a: Vec<Outpoint>,
+    /// Outpoints only present in the second set.
+    pub only_in_b: Vec<Outpoint>,
+    /// Outpoints present in both sets but with a different output.
+    pub changed: Vec<Outpoint>,
+}
+
+impl UtxoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty()
+            && self.only_in_b.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// Compare two UTXO sets, e.g. one loaded from a snapshot with one rebuilt
+/// from the blocks.
+pub fn utxo_diff(a: &UtxoSet, b: &UtxoSet) -> UtxoDiff {
+    let mut diff = UtxoDiff::default();
+    for (outpoint, output) in a.iter() {
+        match b.get(outpoint) {
+            None => diff.only_in_a.push(*outpoint),
+            // the hash covers every field of the output
+            Some(other) if other.hash() != output.hash() => {
+                diff.changed.push(*outpoint)
+            }
+            Some(_) => {}
+        }
+    }
+    diff.only_in_b = b
+        .iter()
+        .map(|(outpoint, _)| *outpoint)
+        .filter(|outpoint| !a.contains(outpoint))
+        .collect();
+    diff.only_in_a.sort();
+    diff.only_in_b.sort();
+    diff.changed.sort();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+    use crate::test_util::output;
+    use crate::types::TransactionOutput;
+    use crate::utxo::Coin;
+    use std::collections::HashMap;
+
+    // `count` distinct transactions, enough for merkle trees
+    fn transactions(count: u64) -> Vec<Transaction> {
+        (0..count)
+            .map(|height| Transaction::coinbase(height, vec![]))
+            .collect()
+    }
+
+    #[test]
+    fn merkle_verifier_matches_calculate() {
+        for count in 1..=9 {
+            let transactions = transactions(count);
+            let root = MerkleRoot::calculate(&transactions);
+            let mut verifier = MerkleVerifier::new(root);
+            for transaction in &transactions {
+                verifier.push(transaction);
+            }
+            assert_eq!(verifier.root(), Some(root));
+            assert!(verifier.finish());
+        }
+    }
+
+    #[test]
+    fn merkle_verifier_rejects_missing_or_reordered_transactions() {
+        let transactions = transactions(5);
+        let root = MerkleRoot::calculate(&transactions);
+        assert!(MerkleVerifier::new(root).root().is_none());
+
+        let mut missing = MerkleVerifier::new(root);
+        for transaction in &transactions[..4] {
+            missing.push(transaction);
+        }
+        assert!(!missing.finish());
+        let mut reordered = MerkleVerifier::new(root);
+        for transaction in transactions.iter().rev() {
+            reordered.push(transaction);
+        }
+        assert!(!reordered.finish());
+    }
+
+    #[test]
+    fn a_single_transaction_root_is_its_tagged_leaf() {
+        let transactions = transactions(1);
+        let root = MerkleRoot::calculate(&transactions);
+        assert_eq!(root, MerkleRoot(merkle_leaf(&transactions[0])));
+        assert_ne!(root, MerkleRoot(transactions[0].hash()));
+        let proof = MerkleRoot::proof(&transactions, 0).unwrap();
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(&transactions[0], &root));
+    }
+
+    #[test]
+    fn inner_nodes_are_not_leaves() {
+        let transactions = transactions(2);
+        let leaves: Vec<Hash> = transactions.iter().map(merkle_leaf).collect();
+        let node = merkle_node(leaves[0], leaves[1]);
+        assert_eq!(MerkleRoot::calculate(&transactions), MerkleRoot(node));
+        assert_ne!(merkle_leaf(&[leaves[0], leaves[1]]), node);
+    }
+
+    // A UTXO set holding `outputs`, going through its serialization as a
+    // plain map since it can only be built from blocks otherwise.
+    fn utxo_set(outputs: &[(Outpoint, TransactionOutput)]) -> UtxoSet {
+        let coins: HashMap<Outpoint, Coin> = outputs
+            .iter()
+            .map(|(outpoint, output)| {
+                let coin = Coin {
+                    output: output.clone(),
+                    height: 0,
+                    is_coinbase: false,
+                };
+                (*outpoint, coin)
+            })
+            .collect();
+        ciborium::from_reader(serialize(&coins).as_slice()).unwrap()
+    }
+
+    #[test]
+    fn utxo_diff_sorts_out_missing_and_changed_outputs() {
+        let pubkey = PrivateKey::new_key().public_key();
+        let outpoint = |vout| Outpoint::new(Hash::zero(), vout);
+        let shared = output(10, &pubkey);
+        let a = utxo_set(&[
+            (outpoint(0), shared.clone()),
+            (outpoint(2), output(20, &pubkey)),
+            (outpoint(3), output(30, &pubkey)),
+            (outpoint(1), output(40, &pubkey)),
+        ]);
+        let b = utxo_set(&[
+            (outpoint(0), shared),
+            (outpoint(1), output(40, &pubkey)),
+            (outpoint(4), output(50, &pubkey)),
+        ]);
+        let diff = utxo_diff(&a, &b);
+        assert_eq!(diff.only_in_a, vec![outpoint(2), outpoint(3)]);
+        assert_eq!(diff.only_in_b, vec![outpoint(4)]);
+        // a different unique id is a different output
+        assert_eq!(diff.changed, vec![outpoint(1)]);
+        assert!(!diff.is_empty());
+        assert!(utxo_diff(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn merkle_proofs_verify_every_leaf_of_an_odd_tree() {
+        let transactions = transactions(5);
+        let root = MerkleRoot::calculate(&transactions);
+        for (index, transaction) in transactions.iter().enumerate() {
+            let proof = MerkleRoot::proof(&transactions, index).unwrap();
+            // 5 leaves take 3 levels, the last one paired with itself
+            assert_eq!(proof.siblings.len(), 3);
+            assert!(proof.verify(transaction, &root));
+            let other = &transactions[(index + 1) % transactions.len()];
+            assert!(!proof.verify(other, &root));
+        }
+        assert!(MerkleRoot::proof(&transactions, 5).is_none());
+    }
+
+    #[test]
+    fn shares_only_need_to_meet_the_share_target() {
+        let share_target = U256::MAX >> 4;
+        let mut header = BlockHeader::new(
+            chrono::Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions(1)),
+            U256::MAX >> 32,
+        );
+        // the target is part of the hash, so the share has to be found
+        // with the block's own target in the header
+        while !check_share(&header, share_target)
+            || header.hash().matches_target(header.target)
+        {
+            header.nonce += 1;
+        }
+        assert!(check_share(&header, share_target));
+        assert!(!check_share(&header, header.target));
+    }
+
+    #[test]
+    fn partial_trees_rebuild_the_root_with_their_matches() {
+        for count in 1..=7 {
+            let transactions = transactions(count);
+            let root = MerkleRoot::calculate(&transactions);
+            let last = count as usize - 1;
+            for matched in [vec![], vec![0], vec![last], vec![0, last]] {
+                let txids: Vec<Hash> = matched
+                    .iter()
+                    .map(|index: &usize| transactions[*index].hash())
+                    .collect();
+                let tree = PartialMerkleTree::new(&transactions, &txids);
+                let (tree_root, matches) = tree.extract_matches().unwrap();
+                assert_eq!(tree_root, root);
+                let mut expected: Vec<(usize, Hash)> = matched
+                    .iter()
+                    .map(|index| (*index, merkle_leaf(&transactions[*index])))
+                    .collect();
+                expected.dedup();
+                assert_eq!(matches, expected);
+
+                let bytes = serialize(&tree);
+                let decoded: PartialMerkleTree =
+                    ciborium::from_reader(bytes.as_slice()).unwrap();
+                assert_eq!(decoded, tree);
+                let mut extra = tree.clone();
+                extra.hashes.push(Hash::zero());
+                assert!(extra.extract_matches().is_none());
+            }
+        }
+        let empty = PartialMerkleTree::new(&[], &[]);
+        assert!(empty.extract_matches().is_none());
+    }
 }
@@ -0,0 +1,158 @@
+use crate::types::{Block, Outpoint, TransactionOutput};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The unspent transaction outputs, keyed by outpoint.
+///
+/// Blocks are applied and undone as a whole, so the chain, the mempool and
+/// the wallet all see the outputs created and spent in the same way. Each
+/// output is kept with where it was created, so the rules depending on it
+/// (coinbase maturity, relative lock times) don't need the blocks, which a
+/// pruned chain no longer has.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct UtxoSet {
+    outputs: HashMap<Outpoint, Coin>,
+}
+
+/// An unspent output with the block that created it, like bitcoin's
+/// `Coin`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Coin {
+    pub output: TransactionOutput,
+    /// Height of the block that created the output.
+    pub height: u64,
+    /// Whether the output was created by that block's coinbase.
+    pub is_coinbase: bool,
+}
+
+/// The outputs a block spent, as returned by `UtxoSet::apply_block`, which
+/// `UtxoSet::undo_block` needs to put them back.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BlockUndo {
+    /// Spent outputs in the order the block spent them.
+    pub spent: Vec<(Outpoint, Coin)>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        UtxoSet::default()
+    }
+
+    pub fn get(&self, outpoint: &Outpoint) -> Option<&TransactionOutput> {
+        self.outputs.get(outpoint).map(|coin| &coin.output)
+    }
+
+    /// The unspent output `outpoint` with where it was created.
+    pub fn coin(&self, outpoint: &Outpoint) -> Option<&Coin> {
+        self.outputs.get(outpoint)
+    }
+
+    pub fn contains(&self, outpoint: &Outpoint) -> bool {
+        self.outputs.contains_key(outpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Every unspent output with its outpoint, in no particular order.
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&Outpoint, &TransactionOutput)> + '_ {
+        self.outputs
+            .iter()
+            .map(|(outpoint, coin)| (outpoint, &coin.output))
+    }
+
+    /// Every unspent output, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &TransactionOutput> + '_ {
+        self.outputs.values().map(|coin| &coin.output)
+    }
+
+    /// Spend the outputs used as inputs by the block at `height` and add
+    /// the ones it creates. Inputs spending unknown outputs are skipped,
+    /// validating the block is up to the caller.
+    pub fn apply_block(&mut self, block: &Block, height: u64) -> BlockUndo {
+        let mut undo = BlockUndo::default();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            for input in &transaction.inputs {
+                if let Some(coin) = self.outputs.remove(&input.prev_output) {
+                    undo.spent.push((input.prev_output, coin));
+                }
+            }
+            let txid = transaction.hash();
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                let coin = Coin {
+                    output: output.clone(),
+                    height,
+                    is_coinbase: index == 0,
+                };
+                self.outputs.insert(Outpoint::new(txid, vout as u32), coin);
+            }
+        }
+        undo
+    }
+
+    /// Revert `apply_block` for the last block applied: remove the outputs
+    /// it created and restore the ones it spent, from `undo`.
+    pub fn undo_block(&mut self, block: &Block, undo: &BlockUndo) {
+        for transaction in block.transactions.iter().rev() {
+            let txid = transaction.hash();
+            for vout in 0..transaction.outputs.len() {
+                self.outputs.remove(&Outpoint::new(txid, vout as u32));
+            }
+        }
+        for (outpoint, coin) in &undo.spent {
+            self.outputs.insert(*outpoint, coin.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+    use crate::test_util::{self, output, spend};
+
+    #[test]
+    fn undoing_a_block_restores_the_set() {
+        let key = PrivateKey::new_key();
+        let chain = test_util::chain(&key.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let transaction = spend(
+            &key,
+            &[funds],
+            vec![
+                output(1000, &key.public_key()),
+                output(2000, &key.public_key()),
+            ],
+        );
+        let txid = transaction.hash();
+        let block =
+            test_util::next_block(&chain, vec![transaction], &key.public_key());
+
+        let mut utxos = chain.utxos.clone();
+        assert_eq!(utxos.len(), 1);
+        let undo = utxos.apply_block(&block, 1);
+        assert_eq!(utxos.len(), 3);
+        assert!(!utxos.contains(&funds.0));
+        let created = Outpoint::new(txid, 1);
+        assert_eq!(utxos.get(&created).unwrap().value.to_sats(), 2000);
+        let coin = utxos.coin(&created).unwrap();
+        assert_eq!((coin.height, coin.is_coinbase), (1, false));
+        let (coinbase, _) = test_util::coinbase_output(&block);
+        assert!(utxos.coin(&coinbase).unwrap().is_coinbase);
+
+        utxos.undo_block(&block, &undo);
+        assert_eq!(utxos.len(), 1);
+        assert!(!utxos.contains(&created));
+        let restored = utxos.coin(&funds.0).unwrap();
+        assert_eq!((restored.height, restored.is_coinbase), (0, true));
+        assert_eq!(restored.output.value, funds.1);
+    }
+}
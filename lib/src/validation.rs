@@ -0,0 +1,45 @@
+/// A rule broken by a block, as found by `Block::validate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleViolation {
+    /// Short name of the rule, like "bad-signature".
+    pub rule: &'static str,
+    /// Index of the offending transaction in the block, if the rule is
+    /// about a transaction.
+    pub transaction: Option<usize>,
+    /// Index of the offending input in that transaction, if the rule is
+    /// about an input.
+    pub input: Option<usize>,
+}
+
+/// Every rule a block breaks, in the order they were checked, rather than
+/// just the first one like `Block::verify_transactions` reports.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub violations: Vec<RuleViolation>,
+}
+
+impl ValidationReport {
+    pub fn new() -> Self {
+        ValidationReport::default()
+    }
+
+    /// Whether no rule is broken.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Record that `rule` is broken, by the transaction and input at the
+    /// given indices if any.
+    pub fn add(
+        &mut self,
+        rule: &'static str,
+        transaction: Option<usize>,
+        input: Option<usize>,
+    ) {
+        self.violations.push(RuleViolation {
+            rule,
+            transaction,
+            input,
+        });
+    }
+}
@@ -0,0 +1,287 @@
+use crate::amount::Amount;
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::error::{BtcError, Result};
+use crate::sha256::Hash;
+use crate::types::{
+    Outpoint, Transaction, TransactionInput, TransactionOutput,
+};
+use crate::utxo::UtxoSet;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A wallet holding a single key, spending the outputs paying it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Wallet {
+    private_key: PrivateKey,
+}
+
+impl Wallet {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Wallet { private_key }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.private_key.public_key()
+    }
+
+    /// Build and sign a transaction paying `amount` to `recipient` and
+    /// leaving `fee` to the miner.
+    ///
+    /// The wallet's outputs in `utxos` are picked largest first until they
+    /// cover both; anything left over is sent back to the wallet in a
+    /// change output. Fails with `InsufficientFunds` if they don't.
+    pub fn create_transaction(
+        &self,
+        utxos: &UtxoSet,
+        recipient: &PublicKey,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let public_key = self.public_key();
+        let needed =
+            amount.checked_add(fee).ok_or(BtcError::InsufficientFunds)?;
+        let mut candidates: Vec<(&Outpoint, &TransactionOutput)> = utxos
+            .iter()
+            .filter(|(_, output)| output.pubkey == public_key)
+            .collect();
+        // break ties by outpoint so the selection doesn't depend on the
+        // HashMap
+        candidates.sort_by(|a, b| b.1.value.cmp(&a.1.value).then(a.0.cmp(b.0)));
+
+        let mut selected = vec![];
+        let mut total = Amount::ZERO;
+        for (outpoint, output) in candidates {
+            if total >= needed {
+                break;
+            }
+            // the UTXO values can't add up to more than the supply
+            total = total.checked_add(output.value).unwrap();
+            selected.push((*outpoint, output.value));
+        }
+        self.build_transaction(&selected, recipient, amount, needed)
+    }
+
+    /// Like `create_transaction`, but spending exactly the outputs at
+    /// `outpoints` instead of picking them, with any excess going back to
+    /// the wallet as change.
+    ///
+    /// Fails with `InvalidTransactionInput` if an outpoint is repeated, not
+    /// in `utxos` or pays someone else, and with `InsufficientFunds` if the
+    /// outputs don't cover `amount` and `fee`.
+    pub fn create_transaction_from(
+        &self,
+        utxos: &UtxoSet,
+        outpoints: &[Outpoint],
+        recipient: &PublicKey,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let public_key = self.public_key();
+        let needed =
+            amount.checked_add(fee).ok_or(BtcError::InsufficientFunds)?;
+        let mut selected: Vec<(Outpoint, Amount)> = vec![];
+        for outpoint in outpoints {
+            let output = utxos
+                .get(outpoint)
+                .filter(|output| output.pubkey == public_key)
+                .ok_or(BtcError::InvalidTransactionInput)?;
+            if selected.iter().any(|(spent, _)| spent == outpoint) {
+                return Err(BtcError::InvalidTransactionInput);
+            }
+            selected.push((*outpoint, output.value));
+        }
+        self.build_transaction(&selected, recipient, amount, needed)
+    }
+
+    // Sign a transaction spending `selected` (outpoint and value of each
+    // output) to pay `amount` to `recipient`, sending what's left over
+    // `needed` back to the wallet. Fails with `InsufficientFunds` if they
+    // are worth less than `needed`.
+    fn build_transaction(
+        &self,
+        selected: &[(Outpoint, Amount)],
+        recipient: &PublicKey,
+        amount: Amount,
+        needed: Amount,
+    ) -> Result<Transaction> {
+        let public_key = self.public_key();
+        // the UTXO values can't add up to more than the supply
+        let total =
+            Amount::checked_sum(selected.iter().map(|(_, value)| *value))
+                .unwrap();
+        let Some(change) = total.checked_sub(needed) else {
+            return Err(BtcError::InsufficientFunds);
+        };
+
+        let mut outputs = vec![TransactionOutput {
+            value: amount,
+            unique_id: Uuid::new_v4(),
+            pubkey: recipient.clone(),
+        }];
+        if change > Amount::ZERO {
+            outputs.push(TransactionOutput {
+                value: change,
+                unique_id: Uuid::new_v4(),
+                pubkey: public_key,
+            });
+        }
+        // the inputs need a signature to be built, it is replaced once the
+        // transaction is complete and the sighashes known
+        let placeholder =
+            Signature::sign_output(&Hash::zero(), &self.private_key);
+        let inputs = selected
+            .iter()
+            .map(|(outpoint, _)| {
+                TransactionInput::new(*outpoint, placeholder.clone())
+            })
+            .collect();
+        let mut transaction = Transaction::new(inputs, outputs);
+        for (index, (_, value)) in selected.iter().enumerate() {
+            transaction.sign_input(index, *value, &self.private_key)?;
+        }
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    // A chain where the wallet owns three outputs worth 1000, 2000 and
+    // 3000 sats.
+    fn funded_wallet() -> (Wallet, Vec<Outpoint>, UtxoSet) {
+        let wallet = Wallet::new(PrivateKey::new_key());
+        let mut chain = test_util::chain(&wallet.public_key());
+        let funds = test_util::coinbase_output(&chain.blocks[0]);
+        let outputs = [1000, 2000, 3000]
+            .iter()
+            .map(|value| test_util::output(*value, &wallet.public_key()))
+            .collect();
+        let transaction =
+            test_util::spend(&wallet.private_key, &[funds], outputs);
+        let txid = transaction.hash();
+        let miner = PrivateKey::new_key().public_key();
+        let block = test_util::next_block(&chain, vec![transaction], &miner);
+        chain.add_block(block).unwrap();
+        let outpoints = (0..3).map(|vout| Outpoint::new(txid, vout)).collect();
+        (wallet, outpoints, chain.utxos)
+    }
+
+    #[test]
+    fn manual_selection_spends_exactly_the_given_outpoints() {
+        let (wallet, outpoints, utxos) = funded_wallet();
+        let recipient = PrivateKey::new_key().public_key();
+        let chosen = [outpoints[0], outpoints[1]];
+        let transaction = wallet
+            .create_transaction_from(
+                &utxos,
+                &chosen,
+                &recipient,
+                Amount::from_sats(2500),
+                Amount::from_sats(100),
+            )
+            .unwrap();
+        let spent: Vec<Outpoint> = transaction
+            .inputs
+            .iter()
+            .map(|input| input.prev_output)
+            .collect();
+        assert_eq!(spent, chosen);
+        assert_eq!(transaction.outputs[1].value, Amount::from_sats(400));
+        transaction.verify(&utxos).unwrap();
+    }
+
+    #[test]
+    fn manual_selection_fails_if_it_does_not_cover_the_payment() {
+        let (wallet, outpoints, utxos) = funded_wallet();
+        let recipient = PrivateKey::new_key().public_key();
+        // the largest output alone would be enough for the automatic one
+        let result = wallet.create_transaction_from(
+            &utxos,
+            &outpoints[..2],
+            &recipient,
+            Amount::from_sats(2950),
+            Amount::from_sats(100),
+        );
+        assert!(matches!(result, Err(BtcError::InsufficientFunds)));
+        assert!(wallet
+            .create_transaction(
+                &utxos,
+                &recipient,
+                Amount::from_sats(2950),
+                Amount::from_sats(100),
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn manual_selection_rejects_unknown_and_repeated_outpoints() {
+        let (wallet, outpoints, utxos) = funded_wallet();
+        let recipient = PrivateKey::new_key().public_key();
+        let unknown = Outpoint::new(Hash::zero(), 0);
+        for chosen in [vec![unknown], vec![outpoints[2], outpoints[2]]] {
+            let result = wallet.create_transaction_from(
+                &utxos,
+                &chosen,
+                &recipient,
+                Amount::from_sats(10),
+                Amount::ZERO,
+            );
+            assert!(matches!(result, Err(BtcError::InvalidTransactionInput)));
+        }
+    }
+
+    #[test]
+    fn exact_selections_have_no_change_output() {
+        let (wallet, outpoints, utxos) = funded_wallet();
+        let recipient = PrivateKey::new_key().public_key();
+        let transaction = wallet
+            .create_transaction(
+                &utxos,
+                &recipient,
+                Amount::from_sats(2900),
+                Amount::from_sats(100),
+            )
+            .unwrap();
+        assert_eq!(transaction.inputs.len(), 1);
+        assert_eq!(transaction.inputs[0].prev_output, outpoints[2]);
+        assert_eq!(transaction.outputs.len(), 1);
+        assert!(transaction.outputs[0].pubkey == recipient);
+        transaction.verify(&utxos).unwrap();
+    }
+
+    #[test]
+    fn the_excess_goes_back_as_change() {
+        let (wallet, _, utxos) = funded_wallet();
+        let recipient = PrivateKey::new_key().public_key();
+        let transaction = wallet
+            .create_transaction(
+                &utxos,
+                &recipient,
+                Amount::from_sats(3500),
+                Amount::from_sats(100),
+            )
+            .unwrap();
+        // largest first: 3000 and 2000
+        assert_eq!(transaction.inputs.len(), 2);
+        assert_eq!(transaction.outputs[0].value, Amount::from_sats(3500));
+        assert_eq!(transaction.outputs[1].value, Amount::from_sats(1400));
+        assert!(transaction.outputs[1].pubkey == wallet.public_key());
+        assert_eq!(transaction.fee(&utxos).unwrap(), Amount::from_sats(100));
+        transaction.verify(&utxos).unwrap();
+    }
+
+    #[test]
+    fn spending_more_than_the_balance_fails() {
+        let (wallet, _, utxos) = funded_wallet();
+        let recipient = PrivateKey::new_key().public_key();
+        let result = wallet.create_transaction(
+            &utxos,
+            &recipient,
+            Amount::from_sats(5901),
+            Amount::from_sats(100),
+        );
+        assert!(matches!(result, Err(BtcError::InsufficientFunds)));
+    }
+}